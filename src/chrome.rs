@@ -0,0 +1,476 @@
+//! Fetching pages with a pool of headless Chromes.
+
+use async_trait::async_trait;
+use headless_chrome::browser::ConnectionClosed;
+use headless_chrome::protocol::cdp::types::Event;
+use headless_chrome::protocol::cdp::Network::{Headers, ResourceType, Response};
+use headless_chrome::protocol::cdp::Target::DisposeBrowserContext;
+use headless_chrome::util::Timeout;
+use headless_chrome::{Browser, LaunchOptions, Tab};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+use crate::cookie;
+use crate::fetcher::Fetcher;
+use crate::wapp::{self, RawData, Redirect};
+use crate::{ScanConfig, Technologies, WappError};
+
+/// Loads pages in headless Chromes, each in a fresh incognito tab, so the
+/// scripts run and the rules see the rendered page. The browsers only start
+/// once however many pages are fetched, and again if their connection closes.
+pub struct ChromeFetcher {
+    pool: Arc<Pool>,
+    navigation_timeout: Duration,
+    user_agent: Option<String>,
+}
+
+impl ChromeFetcher {
+    /// Launches [`ScanConfig::browsers`] browsers. Chrome picks a free
+    /// debugging port, so several browsers and scanners can run side by side.
+    pub fn new(config: &ScanConfig) -> Result<ChromeFetcher, WappError> {
+        let instances = (0..config.browsers.max(1))
+            .map(|_| Instance::launch(config).map(Mutex::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ChromeFetcher {
+            pool: Arc::new(Pool {
+                config: config.clone(),
+                instances,
+                next: AtomicUsize::new(0),
+            }),
+            navigation_timeout: config.navigation_timeout,
+            user_agent: config.user_agent.clone(),
+        })
+    }
+}
+
+/// A browser, and a tab of its default context through which the contexts of
+/// the visits are disposed of: headless_chrome only sends browser-level calls
+/// of its own.
+#[derive(Clone)]
+struct Instance {
+    browser: Browser,
+    control: Arc<Tab>,
+}
+
+impl Instance {
+    fn launch(config: &ScanConfig) -> Result<Instance, WappError> {
+        let args: Vec<&OsStr> = config.args.iter().map(OsStr::new).collect();
+        let options = LaunchOptions::default_builder()
+            .path(config.chrome_path.clone())
+            .headless(config.headless)
+            .window_size(config.window_size)
+            .args(args)
+            .idle_browser_timeout(config.idle_timeout)
+            .sandbox(config.sandbox)
+            .build()
+            .map_err(|err| WappError::Other(err.to_string()))?;
+        let browser =
+            Browser::new(options).map_err(|err| WappError::BrowserLaunch(err.to_string()))?;
+        let control = browser
+            .new_tab()
+            .map_err(|err| WappError::BrowserLaunch(err.to_string()))?;
+        Ok(Instance { browser, control })
+    }
+}
+
+/// The browsers fetches take turns between.
+struct Pool {
+    config: ScanConfig,
+    instances: Vec<Mutex<Instance>>,
+    next: AtomicUsize,
+}
+
+impl Pool {
+    /// The next browser in turn. headless_chrome drops the connection to a
+    /// browser that has sent nothing for [`ScanConfig::idle_timeout`], after
+    /// which every call fails with [`ConnectionClosed`], so such a browser is
+    /// relaunched first.
+    fn instance(&self) -> Result<Instance, WappError> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut instance = self.instances[next % self.instances.len()]
+            .lock()
+            .map_err(|_| WappError::Other("the browser pool was poisoned".to_string()))?;
+        if matches!(instance.browser.get_version(), Err(err) if err.is::<ConnectionClosed>()) {
+            *instance = Instance::launch(&self.config)?;
+        }
+        Ok(instance.clone())
+    }
+}
+
+/// Closes the tab and disposes of its context when the visit is done, however
+/// it ended; Chrome keeps a context until told otherwise.
+struct VisitGuard {
+    tab: Arc<Tab>,
+    control: Arc<Tab>,
+    context_id: String,
+}
+
+impl Drop for VisitGuard {
+    fn drop(&mut self) {
+        let _ = self.tab.close(false);
+        let _ = self.control.call_method(DisposeBrowserContext {
+            browser_context_id: self.context_id.clone(),
+        });
+    }
+}
+
+#[async_trait]
+impl Fetcher for ChromeFetcher {
+    async fn fetch(
+        &self,
+        url: &Url,
+        technologies: &Arc<Technologies>,
+    ) -> Result<RawData, WappError> {
+        // Every call to Chrome blocks until it answers, so the whole visit,
+        // from picking the browser to closing the tab, runs off the async
+        // threads. The scan timeout can then give up on it without holding up
+        // other scans.
+        let pool = self.pool.clone();
+        let navigation_timeout = self.navigation_timeout;
+        let user_agent = self.user_agent.clone();
+        let url = url.clone();
+        let technologies = technologies.clone();
+        tokio::task::spawn_blocking(move || {
+            visit(
+                &pool.instance()?,
+                url,
+                &technologies,
+                navigation_timeout,
+                user_agent.as_deref(),
+            )
+        })
+        .await
+        .unwrap_or_else(|err| Err(WappError::Other(err.to_string())))
+    }
+}
+
+/// Loads `url` in a new tab of `instance` and closes the tab afterwards.
+fn visit(
+    instance: &Instance,
+    url: Url,
+    technologies: &Technologies,
+    navigation_timeout: Duration,
+    user_agent: Option<&str>,
+) -> Result<RawData, WappError> {
+    // A context of its own keeps cookies and cache from leaking between scans.
+    let context = instance
+        .browser
+        .new_context()
+        .map_err(|err| WappError::Fetch(err.to_string()))?;
+    let tab = context
+        .new_tab()
+        .map_err(|err| WappError::Fetch(err.to_string()))?;
+    let _guard = VisitGuard {
+        tab: tab.clone(),
+        control: instance.control.clone(),
+        context_id: context.get_id().to_string(),
+    };
+    tab.set_default_timeout(navigation_timeout);
+    if let Some(user_agent) = user_agent {
+        tab.set_user_agent(user_agent, None, None)
+            .map_err(|err| WappError::Fetch(err.to_string()))?;
+    }
+    fetch_tab(&tab, url, technologies)
+}
+
+fn get_html(tab: &Tab) -> Option<String> {
+    let remote_object = tab
+        .evaluate("document.documentElement.outerHTML", false)
+        .ok()?;
+
+    let json = remote_object.value?;
+    let str = json.as_str()?;
+
+    Some(str.to_owned())
+}
+
+/// Looks up each property path from `window`, the way Wappalyzer does, and
+/// returns the defined ones as a JSON object of strings.
+const JS_PROPERTIES_SCRIPT: &str = r#"(function (paths) {
+    var found = {};
+    paths.forEach(function (path) {
+        try {
+            var value = path.split('.').reduce(function (object, key) {
+                return object !== undefined && object !== null ? object[key] : undefined;
+            }, window);
+            if (value !== undefined) {
+                found[path] = typeof value === 'string' || typeof value === 'number'
+                    ? String(value)
+                    : String(!!value);
+            }
+        } catch (e) {}
+    });
+    return JSON.stringify(found);
+})"#;
+
+/// Reads each property from the elements its selector matches and returns the
+/// first defined value of each as a JSON object of objects of strings, by
+/// selector and property.
+const DOM_PROPERTIES_SCRIPT: &str = r#"(function (rules) {
+    var found = {};
+    rules.forEach(function (rule) {
+        var selector = rule[0], property = rule[1];
+        try {
+            var elements = document.querySelectorAll(selector);
+            for (var i = 0; i < elements.length; i++) {
+                var value = elements[i][property];
+                if (value !== undefined && value !== null) {
+                    found[selector] = found[selector] || {};
+                    found[selector][property] = typeof value === 'string' || typeof value === 'number'
+                        ? String(value)
+                        : String(!!value);
+                    break;
+                }
+            }
+        } catch (e) {}
+    });
+    return JSON.stringify(found);
+})"#;
+
+fn get_dom(
+    tab: &Tab,
+    technologies: &Technologies,
+) -> Option<HashMap<String, HashMap<String, String>>> {
+    let properties = technologies.dom_properties();
+    if properties.is_empty() {
+        return None;
+    }
+    let rules = serde_json::to_string(&properties).ok()?;
+    let remote_object = tab
+        .evaluate(&format!("{}({})", DOM_PROPERTIES_SCRIPT, rules), false)
+        .ok()?;
+
+    let json = remote_object.value?;
+    serde_json::from_str(json.as_str()?).ok()
+}
+
+fn get_js(tab: &Tab, technologies: &Technologies) -> Option<HashMap<String, String>> {
+    let paths = serde_json::to_string(&technologies.js_properties()).ok()?;
+    let remote_object = tab
+        .evaluate(&format!("{}({})", JS_PROPERTIES_SCRIPT, paths), false)
+        .ok()?;
+
+    let json = remote_object.value?;
+    serde_json::from_str(json.as_str()?).ok()
+}
+
+/// The [`WappError`] for a failed navigation. Chrome reports network failures
+/// as `net::ERR_*` codes in the error text, and waiting for a page that
+/// doesn't finish loading in time fails with [`Timeout`].
+fn navigation_error(err: impl fmt::Display) -> WappError {
+    let text = err.to_string();
+    if text == Timeout.to_string() {
+        WappError::Timeout
+    } else if text.contains("ERR_NAME_NOT_RESOLVED") || text.contains("ERR_NAME_RESOLUTION_FAILED")
+    {
+        WappError::Dns(text)
+    } else if ["ERR_CERT_", "ERR_SSL_", "ERR_BAD_SSL"]
+        .iter()
+        .any(|code| text.contains(code))
+    {
+        WappError::Tls(text)
+    } else if text.contains("ERR_TIMED_OUT") || text.contains("ERR_CONNECTION_TIMED_OUT") {
+        WappError::Timeout
+    } else {
+        WappError::Fetch(text)
+    }
+}
+
+/// Converts CDP headers, which are a JSON object, to a map by lowercase name.
+fn headers_map(headers: &Headers) -> HashMap<String, String> {
+    headers
+        .0
+        .as_ref()
+        .and_then(|headers| headers.as_object())
+        .map(|headers| {
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value.as_str() {
+                        Some(value) => value.to_string(),
+                        None => value.to_string(),
+                    };
+                    (name.to_lowercase(), value)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn redirect(response: &Response) -> Redirect {
+    Redirect {
+        url: response.url.clone(),
+        status: u16::try_from(response.status).unwrap_or(u16::MAX),
+        headers: headers_map(&response.headers),
+    }
+}
+
+/// What the network events say about the navigation of the page: the ID of
+/// the request for the page and the redirects along the way, by request ID.
+#[derive(Default)]
+struct Navigation {
+    request_id: Option<String>,
+    redirects: Vec<(String, Redirect)>,
+}
+
+fn fetch_tab(tab: &Tab, url: Url, technologies: &Technologies) -> Result<RawData, WappError> {
+    // The main frame of a page target has the target's ID.
+    let main_frame = tab.get_target_id().clone();
+
+    let navigation = Arc::new(Mutex::new(Navigation::default()));
+    let navigation2 = navigation.clone();
+    let frame = main_frame.clone();
+    tab.add_event_listener(Arc::new(move |event: &Event| {
+        if let Event::NetworkRequestWillBeSent(event) = event {
+            let params = &event.params;
+            if params.frame_id.as_ref() != Some(&frame)
+                || !matches!(params.Type, Some(ResourceType::Document))
+            {
+                return;
+            }
+            if let Ok(mut navigation) = navigation2.lock() {
+                // A navigation request has the ID of its loader, and a redirect
+                // comes with the request that follows it, under the same ID.
+                if params.request_id == params.loader_id {
+                    navigation.request_id = Some(params.request_id.clone());
+                }
+                if let Some(response) = &params.redirect_response {
+                    navigation
+                        .redirects
+                        .push((params.request_id.clone(), redirect(response)));
+                }
+            }
+        }
+    }))
+    .map_err(|err| WappError::Fetch(err.to_string()))?;
+
+    // Only the responses themselves are kept; no rule reads the bodies of
+    // subresources, and fetching them would cost a round trip each.
+    let responses = Arc::new(Mutex::new(Vec::new()));
+    let responses2 = responses.clone();
+
+    tab.enable_response_handling(Box::new(move |response, _| {
+        if let Ok(mut responses) = responses2.lock() {
+            responses.push(response);
+        }
+    }))
+    .map_err(|err| WappError::Fetch(err.to_string()))?;
+    tab.navigate_to(url.as_str()).map_err(navigation_error)?;
+
+    let rendered_tab = tab.wait_until_navigated().map_err(navigation_error)?;
+
+    let html = get_html(rendered_tab).unwrap_or_default();
+    let final_url = rendered_tab.get_url();
+    let js = get_js(rendered_tab, technologies).unwrap_or_default();
+    let dom = get_dom(rendered_tab, technologies).unwrap_or_default();
+
+    let responses = responses
+        .lock()
+        .map_err(|_| WappError::Other("the response log was poisoned".to_string()))?;
+    let navigation = navigation
+        .lock()
+        .map_err(|_| WappError::Other("the navigation log was poisoned".to_string()))?;
+    // Service workers, preloads and frames answer too, so the page is the
+    // response to the navigation request, or else the last document the main
+    // frame received.
+    let document = responses
+        .iter()
+        .find(|response| Some(&response.request_id) == navigation.request_id.as_ref())
+        .or_else(|| {
+            responses.iter().rev().find(|response| {
+                matches!(response.Type, ResourceType::Document)
+                    && response.frame_id.as_ref() == Some(&main_frame)
+            })
+        })
+        .or_else(|| responses.first())
+        .ok_or_else(|| WappError::Fetch("no response was received".to_string()))?;
+    let status = u16::try_from(document.response.status).unwrap_or(u16::MAX);
+    if html.trim().is_empty() && status < 400 {
+        return Err(WappError::EmptyDocument);
+    }
+    let headers = headers_map(&document.response.headers);
+    let redirects: Vec<Redirect> = navigation
+        .redirects
+        .iter()
+        .filter(|(request_id, _)| *request_id == document.request_id)
+        .map(|(_, redirect)| redirect.clone())
+        .collect();
+    let mut cookies: Vec<wapp::Cookie> = tab
+        .get_cookies()
+        .map_err(|err| WappError::Fetch(err.to_string()))?
+        .into_iter()
+        .map(|c| wapp::Cookie {
+            name: c.name,
+            value: c.value,
+            domain: Some(c.domain),
+            path: Some(c.path),
+            // Session cookies expire at -1.
+            expires: Some(c.expires).filter(|expires| *expires >= 0.0),
+            http_only: c.http_only,
+            secure: c.secure,
+            same_site: c.same_site.map(|same_site| format!("{:?}", same_site)),
+        })
+        .collect();
+    let mut raw_data = RawData::from_html_and_headers(&document.response.url, &html, headers);
+    // The jar leaves out cookies the browser rejected or that were set on the
+    // way through redirects to other hosts, but the headers still have them.
+    // The page's own cookies belong to the host it was served from.
+    let page_cookies = wapp::set_cookies(&document.response.url, &raw_data.headers);
+    cookie::merge(&mut cookies, page_cookies);
+    for redirect in &redirects {
+        cookie::merge(
+            &mut cookies,
+            wapp::set_cookies(&redirect.url, &redirect.headers),
+        );
+    }
+    raw_data.cookies = cookies;
+
+    // Scripts injected after load don't always stay in the DOM, but the browser still fetched them.
+    for response in responses.iter() {
+        if matches!(response.Type, ResourceType::Script)
+            && !raw_data.script_srcs.contains(&response.response.url)
+        {
+            raw_data.script_srcs.push(response.response.url.clone());
+        }
+    }
+
+    raw_data.url = url.to_string();
+    raw_data.redirects = redirects;
+    raw_data.js = js;
+    raw_data.dom = dom;
+    raw_data.final_url = final_url;
+    raw_data.status = Some(status);
+    Ok(raw_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_navigation_error() {
+        assert!(matches!(
+            navigation_error("Navigate failed: net::ERR_NAME_NOT_RESOLVED"),
+            WappError::Dns(_)
+        ));
+        assert!(matches!(
+            navigation_error("Navigate failed: net::ERR_CERT_AUTHORITY_INVALID"),
+            WappError::Tls(_)
+        ));
+        assert_eq!(
+            navigation_error("Navigate failed: net::ERR_CONNECTION_TIMED_OUT"),
+            WappError::Timeout
+        );
+        assert_eq!(navigation_error(Timeout), WappError::Timeout);
+        assert!(matches!(
+            navigation_error("Navigate failed: net::ERR_CONNECTION_REFUSED"),
+            WappError::Fetch(_)
+        ));
+    }
+}
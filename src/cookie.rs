@@ -0,0 +1,285 @@
+//! Cookies, from the browser's cookie jar and from `Set-Cookie` headers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A cookie the page set, with its attributes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// The host the cookie belongs to, with a leading `.` when it is shared
+    /// with subdomains.
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    /// When the cookie expires, in seconds since the Unix epoch; `None` for a
+    /// session cookie.
+    pub expires: Option<f64>,
+    pub http_only: bool,
+    pub secure: bool,
+    /// `Strict`, `Lax` or `None`.
+    pub same_site: Option<String>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Cookie::default()
+        }
+    }
+
+    /// Parses the value of a `Set-Cookie` header, e.g.
+    /// `NID=188=E7jf; expires=Thu, 19-Mar-2020 19:05:14 GMT; path=/; HttpOnly`.
+    /// `host` is the host that sent it, the cookie's domain unless the header
+    /// names another one.
+    pub fn parse(set_cookie: &str, host: Option<&str>) -> Option<Cookie> {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie::new(name, value.trim());
+        cookie.domain = host.map(str::to_string);
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    cookie.domain = Some(format!(".{}", domain));
+                }
+                "path" if value.starts_with('/') => cookie.path = Some(value.to_string()),
+                "expires" => cookie.expires = parse_date(value).or(cookie.expires),
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "httponly" => cookie.http_only = true,
+                "secure" => cookie.secure = true,
+                "samesite" if !value.is_empty() => cookie.same_site = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires.
+        if let Some(max_age) = max_age {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs_f64())
+                .unwrap_or_default();
+            cookie.expires = Some(now + max_age as f64);
+        }
+
+        Some(cookie)
+    }
+
+    /// Whether the cookie goes with requests to `path` on `host`: its domain is
+    /// the host, or one of the host's parents when it starts with a `.`, and
+    /// its path is `path` or one of its parents. A missing domain or path
+    /// matches any.
+    pub fn matches(&self, host: &str, path: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        let domain = self.domain.as_deref().map(str::to_ascii_lowercase);
+        let domain_matches = match domain.as_deref() {
+            Some(domain) => match domain.strip_prefix('.') {
+                Some(parent) => host == parent || host.ends_with(domain),
+                None => host == domain,
+            },
+            None => true,
+        };
+        let path_matches = match self.path.as_deref() {
+            Some(prefix) => {
+                path == prefix
+                    || path.starts_with(prefix)
+                        && (prefix.ends_with('/') || path[prefix.len()..].starts_with('/'))
+            }
+            None => true,
+        };
+        domain_matches && path_matches
+    }
+
+    /// Whether the two are the same cookie: the same name, domain and path,
+    /// where a missing domain or path matches any.
+    fn same_as(&self, other: &Cookie) -> bool {
+        let domain = |cookie: &Cookie| {
+            cookie
+                .domain
+                .as_deref()
+                .map(|domain| domain.trim_start_matches('.').to_ascii_lowercase())
+        };
+        let same = |a: Option<String>, b: Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.name == other.name
+            && same(domain(self), domain(other))
+            && same(self.path.clone(), other.path.clone())
+    }
+}
+
+/// Adds the cookies of `others` that aren't in `cookies` already. The cookies
+/// already there are kept as they are, so the browser's cookies should go
+/// first: it knows every attribute.
+pub fn merge(cookies: &mut Vec<Cookie>, others: impl IntoIterator<Item = Cookie>) {
+    for other in others {
+        if !cookies.iter().any(|cookie| cookie.same_as(&other)) {
+            cookies.push(other);
+        }
+    }
+}
+
+/// Whether a cookie rule's name matches a cookie's name. A rule name ending in
+/// `*`, such as `Fe26.2**`, matches every cookie name it is a prefix of.
+pub fn name_matches(rule: &str, name: &str) -> bool {
+    match rule.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix.trim_end_matches('*')),
+        None => rule == name,
+    }
+}
+
+/// Parses a cookie date as browsers do (RFC 6265, section 5.1.1), which
+/// covers `Wed, 21 Oct 2015 07:28:00 GMT`, `Fri, 18-Oct-2019 19:05:14 GMT` and
+/// the other formats found in the wild. Returns seconds since the Unix epoch.
+fn parse_date(date: &str) -> Option<f64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+
+    let tokens = date
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != ':')
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        let digits = token.chars().take_while(char::is_ascii_digit).count();
+        if time.is_none() && token.contains(':') {
+            let fields: Vec<u32> = token.split(':').filter_map(|f| f.parse().ok()).collect();
+            if let [hour, minute, second] = fields[..] {
+                time = Some((hour, minute, second));
+                continue;
+            }
+        }
+        if day.is_none() && (1..=2).contains(&digits) {
+            day = token[..digits].parse::<u32>().ok();
+        } else if month.is_none() && token.len() >= 3 {
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(index) = MONTHS.iter().position(|m| *m == prefix) {
+                month = Some(index as u32 + 1);
+            }
+        } else if year.is_none() && (2..=4).contains(&digits) {
+            year = token[..digits].parse::<i64>().ok().map(|year| match year {
+                70..=99 => year + 1900,
+                0..=69 => year + 2000,
+                _ => year,
+            });
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, year) = (day?, month?, year?);
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    Some((days * 86_400 + i64::from(hour * 3600 + minute * 60 + second)) as f64)
+}
+
+/// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cookie = Cookie::parse(
+            "1P_JAR=2019-09-18-19; expires=Fri, 18-Oct-2019 19:05:14 GMT; path=/; domain=.google.com; SameSite=none",
+            Some("www.google.com"),
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "1P_JAR");
+        assert_eq!(cookie.value, "2019-09-18-19");
+        assert_eq!(cookie.domain.as_deref(), Some(".google.com"));
+        assert_eq!(cookie.path.as_deref(), Some("/"));
+        assert_eq!(cookie.expires, Some(1_571_425_514.0));
+        assert_eq!(cookie.same_site.as_deref(), Some("none"));
+        assert!(!cookie.http_only);
+
+        let cookie = Cookie::parse("sid=abc; Secure; HttpOnly", Some("example.com")).unwrap();
+        assert_eq!(cookie.domain.as_deref(), Some("example.com"));
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.expires, None);
+
+        assert_eq!(Cookie::parse("no-value", None), None);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(1_445_412_480.0)
+        );
+        assert_eq!(
+            parse_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(784_111_777.0)
+        );
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), Some(784_111_777.0));
+        assert_eq!(parse_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut cookies = vec![Cookie {
+            domain: Some(".example.com".to_string()),
+            path: Some("/".to_string()),
+            http_only: true,
+            ..Cookie::new("sid", "abc")
+        }];
+        merge(
+            &mut cookies,
+            vec![
+                Cookie::parse("sid=abc; path=/", Some("example.com")).unwrap(),
+                Cookie::parse("lang=en", Some("example.com")).unwrap(),
+            ],
+        );
+        assert_eq!(cookies.len(), 2);
+        assert!(cookies[0].http_only);
+        assert_eq!(cookies[1].name, "lang");
+    }
+
+    #[test]
+    fn test_matches() {
+        let cookie = Cookie::parse("sid=abc; domain=example.com; path=/shop", None).unwrap();
+        assert!(cookie.matches("example.com", "/shop"));
+        assert!(cookie.matches("WWW.example.com", "/shop/cart"));
+        assert!(!cookie.matches("example.com", "/shopping"));
+        assert!(!cookie.matches("badexample.com", "/shop"));
+
+        let cookie = Cookie::parse("lang=en", Some("example.com")).unwrap();
+        assert!(cookie.matches("example.com", "/"));
+        assert!(!cookie.matches("www.example.com", "/"));
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("Fe26.2**", "Fe26.2**abc"));
+        assert!(name_matches("Fe26.2**", "Fe26.2"));
+        assert!(!name_matches("Fe26.2**", "Fe26.3"));
+        assert!(name_matches("_session_id", "_session_id"));
+        assert!(!name_matches("_session_id", "_session_id2"));
+    }
+}
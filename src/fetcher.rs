@@ -0,0 +1,403 @@
+//! Ways to get the data of a page for the rules to run on.
+
+use async_trait::async_trait;
+use reqwest::header::COOKIE;
+use reqwest::redirect::Policy;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+use crate::cookie::{self, Cookie};
+use crate::wapp::{self, RawData, Redirect};
+use crate::{ScanConfig, Technologies, WappError};
+
+/// Gets the data of the page at a URL. Implementations trade accuracy for
+/// speed: a [`ChromeFetcher`](crate::ChromeFetcher) runs the page's scripts,
+/// an [`HttpFetcher`] only downloads the HTML, and a [`FileFetcher`] reads
+/// pages saved before.
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Fetches the page at `url`. `technologies` says which JS properties
+    /// the `js` rules look for, for fetchers that can read them.
+    async fn fetch(
+        &self,
+        url: &Url,
+        technologies: &Arc<Technologies>,
+    ) -> Result<RawData, WappError>;
+}
+
+/// How many redirects an [`HttpFetcher`] follows before giving up.
+const MAX_REDIRECTS: usize = 10;
+
+/// How much of a page an [`HttpFetcher`] reads; the rest is left out.
+const MAX_BODY_BYTES: usize = 5 * 1024 * 1024;
+
+/// Downloads pages with a plain HTTP client. Much faster than a browser, but
+/// the rules only see what the server sent: no `js` properties, and nothing
+/// the page's scripts add. Only HTML bodies are read, up to 5 MiB; other
+/// responses are checked by their headers alone. Cookies set along the
+/// redirects are sent on, as a browser would.
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    /// Uses the user agent of `config` and its navigation timeout for each
+    /// request.
+    pub fn new(config: &ScanConfig) -> Result<HttpFetcher, WappError> {
+        // Redirects are followed by hand, to record them.
+        let mut builder = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .timeout(config.navigation_timeout);
+        if let Some(user_agent) = &config.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
+        let client = builder
+            .build()
+            .map_err(|err| WappError::Other(err.to_string()))?;
+        Ok(HttpFetcher { client })
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(
+        &self,
+        url: &Url,
+        _technologies: &Arc<Technologies>,
+    ) -> Result<RawData, WappError> {
+        let mut current = url.clone();
+        let mut redirects = vec![];
+        let mut jar: Vec<Cookie> = vec![];
+        loop {
+            let mut request = self.client.get(current.clone());
+            if let Some(cookies) = cookie_header(&jar, &current) {
+                request = request.header(COOKIE, cookies);
+            }
+            let response = request.send().await.map_err(request_error)?;
+            let status = response.status().as_u16();
+            let headers =
+                headers_map(response.headers().iter().map(|(name, value)| {
+                    (name.as_str(), String::from_utf8_lossy(value.as_bytes()))
+                }));
+
+            let location = headers
+                .get("location")
+                .filter(|_| response.status().is_redirection())
+                .and_then(|location| current.join(location).ok());
+            if let Some(location) = location {
+                if redirects.len() == MAX_REDIRECTS {
+                    return Err(WappError::Fetch("too many redirects".to_string()));
+                }
+                // Cookies set again replace the ones they were set over.
+                let mut cookies = wapp::set_cookies(current.as_str(), &headers);
+                cookie::merge(&mut cookies, jar.drain(..));
+                jar = cookies;
+                redirects.push(Redirect {
+                    url: current.to_string(),
+                    status,
+                    headers,
+                });
+                current = location;
+                continue;
+            }
+
+            let is_html = is_html(&headers);
+            let html = if is_html {
+                read_body(response).await?
+            } else {
+                String::new()
+            };
+            // An error page may well be empty, and is still worth its headers.
+            if is_html && html.trim().is_empty() && status < 400 {
+                return Err(WappError::EmptyDocument);
+            }
+
+            let mut raw_data = RawData::from_html_and_headers(current.as_str(), &html, headers);
+            for redirect in &redirects {
+                cookie::merge(
+                    &mut raw_data.cookies,
+                    wapp::set_cookies(&redirect.url, &redirect.headers),
+                );
+            }
+            raw_data.url = url.to_string();
+            raw_data.redirects = redirects;
+            raw_data.status = Some(status);
+            return Ok(raw_data);
+        }
+    }
+}
+
+/// Whether a response is a page, going by its `Content-Type`. Servers that
+/// don't send one are taken to send HTML.
+fn is_html(headers: &HashMap<String, String>) -> bool {
+    headers
+        .get("content-type")
+        .is_none_or(|content_type| content_type.to_lowercase().contains("html"))
+}
+
+/// Reads the body of `response`, up to [`MAX_BODY_BYTES`], as UTF-8 with any
+/// invalid bytes replaced.
+async fn read_body(mut response: reqwest::Response) -> Result<String, WappError> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(request_error)? {
+        let room = MAX_BODY_BYTES - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if body.len() == MAX_BODY_BYTES {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// The `Cookie` header for a request to `url`, with the cookies of `jar` that
+/// go with it.
+fn cookie_header(jar: &[Cookie], url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let cookies: Vec<String> = jar
+        .iter()
+        .filter(|cookie| cookie.matches(host, url.path()))
+        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+        .collect();
+    if cookies.is_empty() {
+        None
+    } else {
+        Some(cookies.join("; "))
+    }
+}
+
+/// Converts response headers to a map by lowercase name. Repeated headers are
+/// joined with newlines, the way Chrome reports them.
+pub(crate) fn headers_map<N, V>(
+    headers: impl IntoIterator<Item = (N, V)>,
+) -> HashMap<String, String>
+where
+    N: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut map: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+        let value = value.as_ref();
+        map.entry(name.as_ref().to_lowercase())
+            .and_modify(|values| {
+                values.push('\n');
+                values.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    map
+}
+
+/// Sorts a failed request into the causes callers can act on. The client
+/// only tells timeouts apart, so the rest goes by the text of the error and
+/// its causes.
+fn request_error(err: reqwest::Error) -> WappError {
+    if err.is_timeout() {
+        return WappError::Timeout;
+    }
+    let mut causes = String::new();
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        causes.push_str(&cause.to_string().to_lowercase());
+        causes.push('\n');
+        source = cause.source();
+    }
+    let text = err.to_string();
+    let lowercase = format!("{}\n{}", text.to_lowercase(), causes);
+    if lowercase.contains("dns error") || lowercase.contains("failed to lookup address") {
+        WappError::Dns(text)
+    } else if ["certificate", "ssl", "tls"]
+        .iter()
+        .any(|word| lowercase.contains(word))
+    {
+        WappError::Tls(text)
+    } else {
+        WappError::Fetch(text)
+    }
+}
+
+/// Reads pages saved to disk, from `file://` URLs. The headers are read from
+/// a file next to the page with `.headers` added to its name, if there is
+/// one, as written by `curl -D`: a `Name: value` line per header. When it
+/// holds several responses, as with `curl -L`, the last one is the page's.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileFetcher;
+
+#[async_trait]
+impl Fetcher for FileFetcher {
+    async fn fetch(
+        &self,
+        url: &Url,
+        _technologies: &Arc<Technologies>,
+    ) -> Result<RawData, WappError> {
+        let path = url
+            .to_file_path()
+            .map_err(|_| WappError::Fetch(format!("{} is not a file URL", url)))?;
+        // Saved pages aren't always UTF-8; invalid bytes are replaced.
+        let bytes = tokio::fs::read(&path)
+            .await
+            .map_err(|err| WappError::Fetch(format!("{}: {}", path.display(), err)))?;
+        let html = String::from_utf8_lossy(&bytes);
+        if html.trim().is_empty() {
+            return Err(WappError::EmptyDocument);
+        }
+
+        let mut headers_path = path.into_os_string();
+        headers_path.push(".headers");
+        let headers = match tokio::fs::read_to_string(&headers_path).await {
+            Ok(text) => parse_headers(&text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(WappError::Fetch(err.to_string())),
+        };
+
+        Ok(RawData::from_html_and_headers(url.as_str(), &html, headers))
+    }
+}
+
+/// Parses the headers of the last response in `text`.
+fn parse_headers(text: &str) -> HashMap<String, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines
+        .iter()
+        .rposition(|line| line.starts_with("HTTP/"))
+        .map_or(0, |status_line| status_line + 1);
+    headers_map(
+        lines[start..]
+            .iter()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves `responses` in turn, one per connection, on a local port, and
+    /// keeps the requests it received.
+    async fn serve(responses: Vec<&'static str>) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 4096];
+                let length = stream.read(&mut request).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&request[..length]).into_owned();
+                received.lock().unwrap().push(request);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                let _ = stream.shutdown().await;
+            }
+        });
+        let url = Url::parse(&format!("http://{}/", address)).unwrap();
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_http_fetcher() {
+        let (url, requests) = serve(vec![
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /home\r\nServer: nginx\r\nSet-Cookie: visited=1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nX-Powered-By: PHP/8.2\r\nSet-Cookie: PHPSESSID=abc\r\nSet-Cookie: lang=en\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n<html><head><meta name=\"generator\" content=\"WordPress 6.4\"></head></html>",
+        ])
+        .await;
+        let fetcher = HttpFetcher::new(&ScanConfig::default()).unwrap();
+        let raw_data = fetcher
+            .fetch(&url, &Technologies::embedded())
+            .await
+            .unwrap();
+
+        assert_eq!(raw_data.url, url.as_str());
+        assert_eq!(raw_data.final_url, url.join("/home").unwrap().as_str());
+        assert_eq!(raw_data.redirects.len(), 1);
+        assert_eq!(raw_data.status, Some(200));
+        assert_eq!(raw_data.redirects[0].status, 301);
+        assert_eq!(raw_data.redirects[0].headers["server"], "nginx");
+        assert_eq!(raw_data.headers["x-powered-by"], "PHP/8.2");
+        assert_eq!(raw_data.meta("generator"), ["WordPress 6.4"]);
+        let cookies: Vec<&str> = raw_data.cookies.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(cookies, ["PHPSESSID", "lang", "visited"]);
+        // The cookie the redirect set goes with the next request.
+        let requests = requests.lock().unwrap().clone();
+        assert!(!requests[0].to_lowercase().contains("cookie:"));
+        assert!(requests[1].to_lowercase().contains("cookie: visited=1"));
+
+        let (url, _) = serve(vec![
+            "HTTP/1.1 404 Not Found\r\nServer: nginx\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+        let raw_data = fetcher
+            .fetch(&url, &Technologies::embedded())
+            .await
+            .unwrap();
+        assert_eq!(raw_data.status, Some(404));
+        assert_eq!(raw_data.headers["server"], "nginx");
+
+        // Only HTML is read; other responses keep their headers.
+        let (url, _) = serve(vec![
+            "HTTP/1.1 200 OK\r\nServer: nginx\r\nContent-Type: application/pdf\r\nConnection: close\r\n\r\n%PDF-1.7 <html>",
+        ])
+        .await;
+        let raw_data = fetcher
+            .fetch(&url, &Technologies::embedded())
+            .await
+            .unwrap();
+        assert_eq!(raw_data.html, "");
+        assert_eq!(raw_data.headers["server"], "nginx");
+    }
+
+    #[tokio::test]
+    async fn test_file_fetcher() {
+        let dir = std::env::temp_dir().join(format!("wappalyzer-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("index.html");
+        // Latin-1, as old pages often are.
+        std::fs::write(
+            &page,
+            b"<html><title>Caf\xe9</title><script src=\"app.js\"></script></html>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("index.html.headers"),
+            "HTTP/1.1 302 Found\r\nServer: nginx\r\n\r\nHTTP/2 200\r\nserver: cloudflare\r\nset-cookie: a=1\r\nset-cookie: b=2\r\n",
+        )
+        .unwrap();
+
+        let url = Url::from_file_path(&page).unwrap();
+        let raw_data = FileFetcher
+            .fetch(&url, &Technologies::embedded())
+            .await
+            .unwrap();
+        assert!(raw_data.html.contains("Caf\u{fffd}"));
+        assert_eq!(raw_data.headers["server"], "cloudflare");
+        assert_eq!(raw_data.headers["set-cookie"], "a=1\nb=2");
+        assert_eq!(raw_data.cookies.len(), 2);
+        assert_eq!(
+            raw_data.script_srcs,
+            [url.join("app.js").unwrap().to_string()]
+        );
+
+        // The free-standing scan takes any fetcher too.
+        let analysis = crate::scan_with(FileFetcher, url.clone(), None, None, None).await;
+        let techs = analysis.result.unwrap();
+        assert!(techs.iter().any(|tech| tech.name == "CloudFlare"));
+
+        let missing = Url::from_file_path(dir.join("missing.html")).unwrap();
+        assert!(matches!(
+            FileFetcher.fetch(&missing, &Technologies::embedded()).await,
+            Err(WappError::Fetch(_))
+        ));
+        let http = Url::parse("http://example.com/").unwrap();
+        assert!(FileFetcher
+            .fetch(&http, &Technologies::embedded())
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,533 @@
+//! Page data from HAR archives, the network logs browsers export.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
+
+use crate::cookie::{self, days_from_civil, Cookie};
+use crate::fetcher;
+use crate::wapp::{self, MatchOptions, RawData, Redirect};
+use crate::{Analysis, Technologies, WappError};
+
+#[derive(Deserialize)]
+struct Har {
+    log: Log,
+}
+
+#[derive(Deserialize)]
+struct Log {
+    #[serde(default)]
+    pages: Vec<Page>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(default)]
+    pageref: Option<String>,
+    request: Request,
+    response: Response,
+    /// Chrome's name for the kind of resource, e.g. `document` or `script`.
+    #[serde(default, rename = "_resourceType")]
+    resource_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    url: String,
+    #[serde(default)]
+    cookies: Vec<HarCookie>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    status: i64,
+    #[serde(default)]
+    headers: Vec<Header>,
+    #[serde(default)]
+    cookies: Vec<HarCookie>,
+    #[serde(default)]
+    content: Content,
+    #[serde(default, rename = "redirectURL")]
+    redirect_url: String,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarCookie {
+    name: String,
+    value: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    domain: Option<String>,
+    #[serde(default)]
+    expires: Option<String>,
+    #[serde(default)]
+    http_only: Option<bool>,
+    #[serde(default)]
+    secure: Option<bool>,
+    #[serde(default)]
+    same_site: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    #[serde(default)]
+    mime_type: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    encoding: Option<String>,
+}
+
+impl Response {
+    fn headers_map(&self) -> HashMap<String, String> {
+        fetcher::headers_map(
+            self.headers
+                .iter()
+                .map(|header| (&header.name, &header.value)),
+        )
+    }
+}
+
+impl Entry {
+    fn is_document(&self) -> bool {
+        match &self.resource_type {
+            Some(resource_type) => resource_type.eq_ignore_ascii_case("document"),
+            None => self.response.content.mime_type.starts_with("text/html"),
+        }
+    }
+
+    fn is_script(&self) -> bool {
+        match &self.resource_type {
+            Some(resource_type) => resource_type.eq_ignore_ascii_case("script"),
+            None => {
+                let mime_type = &self.response.content.mime_type;
+                mime_type.contains("javascript") || mime_type.contains("ecmascript")
+            }
+        }
+    }
+
+    /// Where the response redirects to, if it is a redirect.
+    fn redirect_target(&self) -> Option<Url> {
+        if !(300..400).contains(&self.response.status) {
+            return None;
+        }
+        let location = Some(self.response.redirect_url.as_str())
+            .filter(|url| !url.is_empty())
+            .or_else(|| {
+                self.response
+                    .headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case("location"))
+                    .map(|header| header.value.as_str())
+            })?;
+        Url::parse(&self.request.url).ok()?.join(location).ok()
+    }
+
+    fn host(&self) -> Option<String> {
+        Url::parse(&self.request.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+    }
+
+    /// The cookies the response set, as the archive lists them.
+    fn response_cookies(&self) -> Vec<Cookie> {
+        let host = self.host();
+        self.response
+            .cookies
+            .iter()
+            .map(|cookie| Cookie {
+                domain: cookie.domain.clone().or_else(|| host.clone()),
+                path: cookie.path.clone(),
+                expires: cookie.expires.as_deref().and_then(parse_iso8601),
+                http_only: cookie.http_only.unwrap_or_default(),
+                secure: cookie.secure.unwrap_or_default(),
+                same_site: cookie.same_site.clone(),
+                ..Cookie::new(&cookie.name, &cookie.value)
+            })
+            .collect()
+    }
+}
+
+impl RawData {
+    /// Builds the page data from a HAR archive, as exported by the network
+    /// panel of a browser. The page is the first document in the archive,
+    /// after the redirects it went through; the other entries of the same
+    /// page add the script URLs, and the cookies that the page's host would
+    /// be sent. The bodies of scripts and other subresources are skipped,
+    /// as when scanning.
+    pub fn from_har(text: &str) -> Result<RawData, WappError> {
+        let har: Har = serde_json::from_str(text)
+            .map_err(|err| WappError::Fetch(format!("invalid HAR: {}", err)))?;
+        let page = har.log.pages.first().map(|page| page.id.as_str());
+        let entries: Vec<&Entry> = har
+            .log
+            .entries
+            .iter()
+            .filter(|entry| {
+                page.is_none() || entry.pageref.is_none() || entry.pageref.as_deref() == page
+            })
+            .collect();
+
+        if entries.is_empty() {
+            return Err(WappError::Fetch("the HAR has no entries".to_string()));
+        }
+        let first = entries
+            .iter()
+            .position(|entry| entry.is_document())
+            .ok_or_else(|| WappError::Fetch("the HAR has no document".to_string()))?;
+        let url = entries[first].request.url.clone();
+
+        // Follow the redirects to the page through the entries after them.
+        let mut index = first;
+        let mut chain = vec![first];
+        let mut redirects = vec![];
+        while let Some(target) = entries[index].redirect_target() {
+            let next = entries[index + 1..]
+                .iter()
+                .position(|entry| Url::parse(&entry.request.url).ok().as_ref() == Some(&target));
+            match next {
+                Some(next) => {
+                    let entry = entries[index];
+                    redirects.push(Redirect {
+                        url: entry.request.url.clone(),
+                        status: u16::try_from(entry.response.status).unwrap_or(u16::MAX),
+                        headers: entry.response.headers_map(),
+                    });
+                    index += 1 + next;
+                    chain.push(index);
+                }
+                None => break,
+            }
+        }
+
+        let document = entries[index];
+        let status = u16::try_from(document.response.status).unwrap_or(u16::MAX);
+        let html = body(&document.response.content)?;
+        if html.trim().is_empty() && status < 400 {
+            return Err(WappError::EmptyDocument);
+        }
+
+        let headers = document.response.headers_map();
+        let mut raw_data = RawData::from_html_and_headers(&document.request.url, &html, headers);
+
+        // The cookies the archive lists come with their attributes, so they go
+        // first; the cookies sent with the requests only have a value. Those
+        // of the page and its redirects all count, but other entries may
+        // belong to third parties, so only the cookies set for the page's
+        // host, or sent to it, are kept from them.
+        let (host, path) = match Url::parse(&document.request.url) {
+            Ok(url) => (
+                url.host_str().unwrap_or_default().to_string(),
+                url.path().to_string(),
+            ),
+            Err(_) => (String::new(), "/".to_string()),
+        };
+        let same_site = |cookie: &Cookie| cookie.matches(&host, &path);
+        let mut cookies = document.response_cookies();
+        cookie::merge(&mut cookies, raw_data.cookies);
+        for &at in &chain {
+            cookie::merge(&mut cookies, entries[at].response_cookies());
+        }
+        for redirect in &redirects {
+            cookie::merge(
+                &mut cookies,
+                wapp::set_cookies(&redirect.url, &redirect.headers),
+            );
+        }
+        for (at, entry) in entries.iter().enumerate() {
+            if !chain.contains(&at) {
+                let set = entry.response_cookies().into_iter().filter(same_site);
+                cookie::merge(&mut cookies, set);
+            }
+        }
+        for (at, entry) in entries.iter().enumerate() {
+            if !chain.contains(&at) && entry.host().as_deref() != Some(host.as_str()) {
+                continue;
+            }
+            let sent = entry
+                .request
+                .cookies
+                .iter()
+                .map(|cookie| Cookie::new(&cookie.name, &cookie.value));
+            cookie::merge(&mut cookies, sent);
+        }
+        raw_data.cookies = cookies;
+
+        // Scripts injected after load don't always stay in the DOM, but the
+        // archive still has their requests.
+        for entry in entries.iter().filter(|entry| entry.is_script()) {
+            if !raw_data.script_srcs.contains(&entry.request.url) {
+                raw_data.script_srcs.push(entry.request.url.clone());
+            }
+        }
+
+        raw_data.url = url;
+        raw_data.redirects = redirects;
+        raw_data.status = Some(status);
+        Ok(raw_data)
+    }
+}
+
+/// Detects the technologies in the page of the HAR archive at `path`, with
+/// the built-in [`Technologies`]. See [`RawData::from_har`] for other
+/// definitions.
+pub fn analyze_har(path: impl AsRef<Path>) -> Analysis {
+    analyze_har_with(
+        path,
+        &Technologies::embedded(),
+        MatchOptions::default(),
+        false,
+    )
+}
+
+/// Like [`analyze_har`], with other definitions and match options. A page
+/// that answered with an error status is reported as
+/// [`WappError::HttpStatus`] unless `error_pages` is set, as with
+/// [`ScanConfig::error_pages`](crate::ScanConfig::error_pages).
+pub fn analyze_har_with(
+    path: impl AsRef<Path>,
+    technologies: &Technologies,
+    options: MatchOptions,
+    error_pages: bool,
+) -> Analysis {
+    let path = path.as_ref();
+    let raw_data = std::fs::read_to_string(path)
+        .map_err(|err| WappError::Fetch(format!("{}: {}", path.display(), err)))
+        .and_then(|text| RawData::from_har(&text))
+        .and_then(|raw_data| match raw_data.status {
+            Some(status) if status >= 400 && !error_pages => Err(WappError::HttpStatus(status)),
+            _ => Ok(raw_data),
+        });
+    match raw_data {
+        Ok(raw_data) => crate::analyze_with(technologies, &raw_data, options),
+        Err(err) => Analysis {
+            url: path.display().to_string(),
+            result: Err(err),
+            scan_time: None,
+        },
+    }
+}
+
+/// The text of a response body, which archives may store in base64.
+fn body(content: &Content) -> Result<String, WappError> {
+    let text = content.text.as_deref().unwrap_or_default();
+    match content.encoding.as_deref() {
+        Some("base64") => {
+            let bytes = BASE64
+                .decode(text.trim())
+                .map_err(|err| WappError::Fetch(format!("invalid HAR body: {}", err)))?;
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => Ok(text.to_string()),
+    }
+}
+
+/// Parses an ISO 8601 date and time, e.g. `2024-01-31T12:00:00.000Z`, into
+/// seconds since the Unix epoch.
+fn parse_iso8601(date: &str) -> Option<f64> {
+    let (day, time) = date.trim().split_once('T')?;
+    let mut fields = day.splitn(3, '-');
+    let year: i64 = fields.next()?.parse().ok()?;
+    let month: u32 = fields.next()?.parse().ok()?;
+    let day: u32 = fields.next()?.parse().ok()?;
+
+    // The offset is `Z`, `±hh:mm`, `±hhmm`, `±hh` or missing, for UTC.
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, ""),
+    };
+    let offset = match offset.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            let digits = &offset[1..];
+            let (hours, minutes) = match digits.split_once(':') {
+                Some(fields) => fields,
+                None => (
+                    digits.get(..2)?,
+                    digits
+                        .get(2..)
+                        .filter(|minutes| !minutes.is_empty())
+                        .unwrap_or("0"),
+                ),
+            };
+            let seconds = hours.parse::<f64>().ok()? * 3600.0 + minutes.parse::<f64>().ok()? * 60.0;
+            if *sign == b'+' {
+                seconds
+            } else {
+                -seconds
+            }
+        }
+        _ => 0.0,
+    };
+    let mut fields = time.splitn(3, ':');
+    let hour: f64 = fields.next()?.parse().ok()?;
+    let minute: f64 = fields.next()?.parse().ok()?;
+    let second: f64 = fields.next().unwrap_or("0").parse().ok()?;
+
+    let days = days_from_civil(year, month, day) as f64;
+    Some(days * 86_400.0 + hour * 3600.0 + minute * 60.0 + second - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAR: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "pages": [{"id": "page_1", "title": "Example"}],
+            "entries": [
+                {
+                    "pageref": "page_1",
+                    "_resourceType": "document",
+                    "request": {"method": "GET", "url": "http://example.com/", "headers": [], "cookies": []},
+                    "response": {
+                        "status": 301,
+                        "headers": [{"name": "Server", "value": "nginx"}, {"name": "Location", "value": "https://example.com/"}],
+                        "cookies": [],
+                        "content": {"size": 0, "mimeType": ""},
+                        "redirectURL": "https://example.com/"
+                    }
+                },
+                {
+                    "pageref": "page_1",
+                    "_resourceType": "document",
+                    "request": {"method": "GET", "url": "https://example.com/", "headers": [], "cookies": [{"name": "_ga", "value": "GA1.1.1"}]},
+                    "response": {
+                        "status": 200,
+                        "headers": [
+                            {"name": "X-Powered-By", "value": "PHP/8.2"},
+                            {"name": "Set-Cookie", "value": "PHPSESSID=abc; path=/; HttpOnly"},
+                            {"name": "Set-Cookie", "value": "lang=en"}
+                        ],
+                        "cookies": [
+                            {"name": "PHPSESSID", "value": "abc", "path": "/", "expires": "2030-01-01T00:00:00.000Z", "httpOnly": true, "secure": false}
+                        ],
+                        "content": {
+                            "size": 96,
+                            "mimeType": "text/html",
+                            "encoding": "base64",
+                            "text": "PGh0bWw+PGhlYWQ+PG1ldGEgbmFtZT0iZ2VuZXJhdG9yIiBjb250ZW50PSJXb3JkUHJlc3MgNi40Ij48L2hlYWQ+PC9odG1sPg=="
+                        },
+                        "redirectURL": ""
+                    }
+                },
+                {
+                    "pageref": "page_1",
+                    "_resourceType": "script",
+                    "request": {"method": "GET", "url": "https://cdn.example.com/jquery-3.7.1.min.js", "headers": [], "cookies": [{"name": "cdn_session", "value": "1"}]},
+                    "response": {"status": 200, "headers": [], "cookies": [{"name": "__cf_bm", "value": "x", "domain": ".cdn.example.com"}], "content": {"size": 0, "mimeType": "application/javascript"}, "redirectURL": ""}
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_from_har() {
+        let raw_data = RawData::from_har(HAR).unwrap();
+        assert_eq!(raw_data.url, "http://example.com/");
+        assert_eq!(raw_data.final_url, "https://example.com/");
+        assert_eq!(raw_data.redirects.len(), 1);
+        assert_eq!(raw_data.redirects[0].status, 301);
+        assert_eq!(raw_data.redirects[0].headers["server"], "nginx");
+        assert_eq!(raw_data.headers["x-powered-by"], "PHP/8.2");
+        assert_eq!(raw_data.meta("generator"), ["WordPress 6.4"]);
+        assert_eq!(
+            raw_data.script_srcs,
+            ["https://cdn.example.com/jquery-3.7.1.min.js"]
+        );
+
+        let names: Vec<&str> = raw_data.cookies.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["PHPSESSID", "lang", "_ga"]);
+        let session = &raw_data.cookies[0];
+        assert!(session.http_only);
+        assert_eq!(session.domain.as_deref(), Some("example.com"));
+        assert_eq!(session.expires, Some(1_893_456_000.0));
+
+        let analysis = crate::analyze(&raw_data);
+        let techs = analysis.result.unwrap();
+        for name in ["WordPress", "PHP", "jQuery"] {
+            assert!(techs.iter().any(|tech| tech.name == name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_from_har_errors() {
+        assert!(matches!(RawData::from_har("{}"), Err(WappError::Fetch(_))));
+        assert!(matches!(
+            RawData::from_har(r#"{"log": {"entries": []}}"#),
+            Err(WappError::Fetch(_))
+        ));
+        // Error pages come with their status, and an empty one still counts.
+        let not_found = r#"{"log": {"entries": [{
+            "request": {"url": "https://example.com/"},
+            "response": {"status": 404, "headers": [{"name": "Server", "value": "nginx"}], "content": {"mimeType": "text/html", "text": ""}}
+        }]}}"#;
+        let raw_data = RawData::from_har(not_found).unwrap();
+        assert_eq!(raw_data.status, Some(404));
+        assert_eq!(raw_data.headers["server"], "nginx");
+
+        let path = std::env::temp_dir().join(format!("wappalyzer-{}.har", std::process::id()));
+        std::fs::write(&path, not_found).unwrap();
+        let options = MatchOptions::default();
+        let technologies = Technologies::embedded();
+        let analysis = analyze_har_with(&path, &technologies, options, false);
+        assert_eq!(analysis.result, Err(WappError::HttpStatus(404)));
+        let analysis = analyze_har_with(&path, &technologies, options, true);
+        assert!(analysis
+            .result
+            .unwrap()
+            .iter()
+            .any(|tech| tech.name == "Nginx"));
+        std::fs::remove_file(&path).unwrap();
+
+        let no_document = r#"{"log": {"entries": [{
+            "request": {"url": "https://example.com/app.js"},
+            "response": {"status": 200, "content": {"mimeType": "application/javascript", "text": "1"}}
+        }]}}"#;
+        assert!(matches!(
+            RawData::from_har(no_document),
+            Err(WappError::Fetch(_))
+        ));
+
+        let analysis = analyze_har("/nonexistent.har");
+        assert_eq!(analysis.url, "/nonexistent.har");
+        assert!(analysis.result.is_err());
+    }
+
+    #[test]
+    fn test_parse_iso8601() {
+        assert_eq!(parse_iso8601("2015-10-21T07:28:00Z"), Some(1_445_412_480.0));
+        assert_eq!(
+            parse_iso8601("2015-10-21T09:28:00.500+02:00"),
+            Some(1_445_412_480.5)
+        );
+        assert_eq!(
+            parse_iso8601("2015-10-21T09:28:00+0200"),
+            Some(1_445_412_480.0)
+        );
+        assert_eq!(
+            parse_iso8601("2015-10-21T02:28:00-05"),
+            Some(1_445_412_480.0)
+        );
+        assert_eq!(parse_iso8601("2015-10-21T07:28:00"), Some(1_445_412_480.0));
+        assert_eq!(parse_iso8601("yesterday"), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Version inference for detected technologies.
+//!
+//! Patterns in apps.json can carry tags after a `\;` separator, for example
+//! `^WordPress ?([\d.]+)?\;version:\1`. The `version` tag is a small template
+//! over the capture groups of the pattern: `\1` is replaced with the first
+//! group, and `\1?a:b` yields `a` when the first group matched and `b` otherwise.
+
+use crate::cookie;
+use crate::pattern::Pattern;
+use crate::wapp::{App, MatchOptions, Page};
+
+/// Separates the regex part of a pattern from its tags.
+const TAG_SEPARATOR: &str = "\\;";
+
+/// Versions longer than this are almost always a greedy capture gone wrong.
+const DEFAULT_MAX_LENGTH: usize = 15;
+
+/// The tags that can follow a pattern in apps.json.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PatternTags {
+    pub confidence: Option<u32>,
+    pub version: Option<VersionTemplate>,
+}
+
+/// Splits a raw apps.json pattern into its regex and its tags.
+///
+/// Unknown tags are ignored, as are confidence values that don't parse.
+pub fn split_pattern(pattern: &str) -> (&str, PatternTags) {
+    let mut parts = pattern.split(TAG_SEPARATOR);
+    let regex = parts.next().unwrap_or("");
+    let mut tags = PatternTags::default();
+
+    for part in parts {
+        if let Some((key, value)) = part.split_once(':') {
+            match key {
+                "confidence" => tags.confidence = value.trim().parse().ok(),
+                "version" => tags.version = Some(VersionTemplate::parse(value)),
+                _ => {}
+            }
+        }
+    }
+
+    (regex, tags)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Group(usize),
+    /// `\N?then:else`; the else branch runs to the end of the template.
+    Ternary(usize, VersionTemplate, VersionTemplate),
+}
+
+/// A parsed `version:` tag.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VersionTemplate {
+    tokens: Vec<Token>,
+}
+
+impl VersionTemplate {
+    pub fn parse(template: &str) -> VersionTemplate {
+        let mut tokens = vec![];
+        let mut literal = String::new();
+        let mut rest = template;
+
+        while let Some(c) = rest.chars().next() {
+            if let Some((group, after)) = parse_group(rest) {
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                }
+                match after.strip_prefix('?').and_then(|t| t.split_once(':')) {
+                    Some((then, otherwise)) => {
+                        tokens.push(Token::Ternary(
+                            group,
+                            VersionTemplate::parse(then),
+                            VersionTemplate::parse(otherwise),
+                        ));
+                        rest = "";
+                    }
+                    None => {
+                        tokens.push(Token::Group(group));
+                        rest = after;
+                    }
+                }
+            } else {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+
+        VersionTemplate { tokens }
+    }
+
+    /// Fills in the template from the capture groups of a match. Returns
+    /// `None` when the result is empty.
+    pub fn resolve(&self, groups: &[Option<&str>]) -> Option<String> {
+        self.render(&|group| groups.get(group).copied().flatten())
+    }
+
+    /// Fills in a template that stands on its own, as on an implied app, where
+    /// there are no groups to refer to.
+    pub fn literal(&self) -> Option<String> {
+        self.render(&|_| None)
+    }
+
+    fn render<'t>(&self, group: &dyn Fn(usize) -> Option<&'t str>) -> Option<String> {
+        let mut version = String::new();
+        self.render_into(group, &mut version);
+        let version = version.trim();
+
+        if version.is_empty() {
+            None
+        } else {
+            Some(version.to_string())
+        }
+    }
+
+    fn render_into<'t>(&self, group: &dyn Fn(usize) -> Option<&'t str>, out: &mut String) {
+        for token in &self.tokens {
+            match token {
+                Token::Literal(text) => out.push_str(text),
+                Token::Group(index) => {
+                    if let Some(text) = group(*index) {
+                        out.push_str(text);
+                    }
+                }
+                Token::Ternary(index, then, otherwise) => {
+                    match group(*index).filter(|text| !text.is_empty()) {
+                        Some(_) => then.render_into(group, out),
+                        None => otherwise.render_into(group, out),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses a leading `\N` backreference, returning the group and the remainder.
+fn parse_group(text: &str) -> Option<(usize, &str)> {
+    let digits = text.strip_prefix('\\')?;
+    let end = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    let group = digits[..end].parse().ok()?;
+    Some((group, &digits[end..]))
+}
+
+/// Works out which version of an app a page is running from the `version:`
+/// tags of the app's patterns.
+#[derive(Debug)]
+pub struct VersionInference {
+    max_length: usize,
+}
+
+impl VersionInference {
+    pub fn new_default() -> VersionInference {
+        VersionInference::new(DEFAULT_MAX_LENGTH)
+    }
+
+    pub fn new(max_length: usize) -> VersionInference {
+        VersionInference { max_length }
+    }
+
+    /// Runs every versioned pattern of `app` against the page, and the
+    /// redirects too when `options` say so, and picks the most specific of the
+    /// versions found.
+    pub(crate) fn infer(&self, app: &App, page: &Page, options: MatchOptions) -> Option<String> {
+        let raw_data = page.raw_data;
+        let mut candidates = vec![];
+
+        for (name, pattern) in app.headers() {
+            let name = name.to_lowercase();
+            if let Some(value) = raw_data.headers.get(&name) {
+                self.collect(pattern, value, &mut candidates);
+            }
+            if options.redirect_headers {
+                for redirect in &raw_data.redirects {
+                    if let Some(value) = redirect.headers.get(&name) {
+                        self.collect(pattern, value, &mut candidates);
+                    }
+                }
+            }
+        }
+
+        for pattern in app.html() {
+            self.collect(pattern, &raw_data.html, &mut candidates);
+        }
+
+        for pattern in app.script() {
+            for src in &raw_data.script_srcs {
+                self.collect(pattern, src, &mut candidates);
+            }
+        }
+
+        for pattern in app.scripts() {
+            for script in &raw_data.script_tags {
+                self.collect(pattern, script, &mut candidates);
+            }
+        }
+
+        for (name, patterns) in app.meta() {
+            for value in raw_data.meta(name) {
+                for pattern in patterns {
+                    self.collect(pattern, value, &mut candidates);
+                }
+            }
+        }
+
+        for (name, pattern) in app.cookies() {
+            for cookie in raw_data
+                .cookies
+                .iter()
+                .filter(|c| cookie::name_matches(name, &c.name))
+            {
+                self.collect(pattern, &cookie.value, &mut candidates);
+            }
+        }
+
+        for (name, pattern) in app.js() {
+            if let Some(value) = raw_data.js.get(name) {
+                self.collect(pattern, value, &mut candidates);
+            }
+        }
+
+        for pattern in app.url() {
+            for url in raw_data.urls() {
+                self.collect(pattern, url, &mut candidates);
+            }
+        }
+
+        for (selector, rule) in app.dom() {
+            for element in page.select(rule) {
+                if let Some(pattern) = &rule.text {
+                    let text = element.text().collect::<String>();
+                    self.collect(pattern, &text, &mut candidates);
+                }
+                for (name, pattern) in &rule.attributes {
+                    if let Some(value) = element.value().attr(name) {
+                        self.collect(pattern, value, &mut candidates);
+                    }
+                }
+            }
+            for (name, pattern) in &rule.properties {
+                if let Some(value) = raw_data.dom.get(selector).and_then(|p| p.get(name)) {
+                    self.collect(pattern, value, &mut candidates);
+                }
+            }
+        }
+
+        for pattern in app.text() {
+            self.collect(pattern, &page.text, &mut candidates);
+        }
+
+        self.most_specific(candidates)
+    }
+
+    fn collect(&self, pattern: &Pattern, text: &str, candidates: &mut Vec<String>) {
+        if let Some(version) = pattern.version(text) {
+            if version.len() <= self.max_length {
+                candidates.push(version);
+            }
+        }
+    }
+
+    /// Prefers the version with the most components, then the longest one,
+    /// e.g. `5.2.1` over `5.2` over `5`.
+    pub(crate) fn most_specific(&self, candidates: Vec<String>) -> Option<String> {
+        candidates.into_iter().max_by(|a, b| {
+            let components = |v: &str| v.split('.').filter(|p| !p.is_empty()).count();
+            components(a)
+                .cmp(&components(b))
+                .then_with(|| a.len().cmp(&b.len()))
+                .then_with(|| b.cmp(a))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn resolve(pattern: &str, text: &str) -> Option<String> {
+        let (regex, tags) = split_pattern(pattern);
+        let captures = Regex::new(regex).unwrap().captures(text)?;
+        let groups: Vec<Option<&str>> = captures.iter().map(|m| m.map(|m| m.as_str())).collect();
+        tags.version?.resolve(&groups)
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        let (regex, tags) = split_pattern("^(.+)$\\;version:\\1\\;confidence:50");
+        assert_eq!(regex, "^(.+)$");
+        assert_eq!(tags.confidence, Some(50));
+        assert!(tags.version.is_some());
+
+        let (regex, tags) = split_pattern("\\;confidence:75");
+        assert_eq!(regex, "");
+        assert_eq!(tags.confidence, Some(75));
+        assert_eq!(tags.version, None);
+    }
+
+    #[test]
+    fn test_resolve_backreferences() {
+        assert_eq!(
+            resolve("^WordPress ?([\\d.]+)?\\;version:\\1", "WordPress 5.2.1"),
+            Some("5.2.1".to_string())
+        );
+        assert_eq!(
+            resolve("^WordPress ?([\\d.]+)?\\;version:\\1", "WordPress"),
+            None
+        );
+        assert_eq!(
+            resolve(
+                "jquery-(\\d+)\\.(\\d+)\\.js\\;version:\\1.\\2",
+                "jquery-3.4.js"
+            ),
+            Some("3.4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_ternary() {
+        assert_eq!(
+            resolve("^(.+)$\\;version:\\1?2+:", "anything"),
+            Some("2+".to_string())
+        );
+        assert_eq!(
+            resolve("Mono(\\.NET)?\\;version:\\1?4:3", "Mono"),
+            Some("3".to_string())
+        );
+        assert_eq!(
+            resolve("v(\\d)(-beta)?\\;version:\\2?\\1b:\\1", "v7-beta"),
+            Some("7b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_most_specific() {
+        let inference = VersionInference::new_default();
+        let candidates = vec!["5".to_string(), "5.2.1".to_string(), "5.2".to_string()];
+        assert_eq!(
+            inference.most_specific(candidates),
+            Some("5.2.1".to_string())
+        );
+        assert_eq!(inference.most_specific(vec![]), None);
+    }
+}
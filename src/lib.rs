@@ -1,23 +1,36 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod chrome;
+mod cookie;
+mod fetcher;
+mod har;
 mod heuristics;
+mod pattern;
+mod scanner;
+mod schema;
+mod technologies;
 pub mod wapp;
 
-use headless_chrome::protocol::cdp::Network::GetResponseBodyReturnObject;
-use headless_chrome::{Browser, LaunchOptions, Tab};
-use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::any::Any;
+use std::collections::HashSet;
 use std::fmt;
-use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
 use url::Url;
-use wapp::{RawData, Tech};
+use wapp::{MatchOptions, RawData, Tech};
+
+pub use chrome::ChromeFetcher;
+pub use fetcher::{Fetcher, FileFetcher, HttpFetcher};
+pub use har::{analyze_har, analyze_har_with};
+pub use scanner::{ScanConfig, Scanner};
+pub use schema::{validate_schema, SchemaError};
+pub use technologies::{Conflict, Layer, PatternError, Technologies};
 
 #[derive(PartialEq, Clone, Serialize, Deserialize)]
 pub struct Analysis {
     pub url: String,
-    pub result: Result<HashSet<Tech>, String>,
+    pub result: Result<HashSet<Tech>, WappError>,
     pub scan_time: Option<Duration>,
 }
 
@@ -25,6 +38,8 @@ pub struct Analysis {
 struct AnalysisOutput {
     scan_time_seconds: Option<f64>,
     technologies: Vec<Tech>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<WappError>,
 }
 
 impl From<&Analysis> for AnalysisOutput {
@@ -41,6 +56,7 @@ impl From<&Analysis> for AnalysisOutput {
         AnalysisOutput {
             scan_time_seconds: analysis.scan_time.map(|time| time.as_secs_f64()),
             technologies,
+            error: analysis.result.as_ref().err().cloned(),
         }
     }
 }
@@ -58,6 +74,20 @@ impl fmt::Debug for Analysis {
 /// Possible Errors in the domain_info lib
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum WappError {
+    /// Chrome could not be started.
+    BrowserLaunch(String),
+    /// The host name did not resolve.
+    Dns(String),
+    /// The TLS handshake or the certificate check failed.
+    Tls(String),
+    /// The page did not load in time.
+    Timeout,
+    /// The page answered with an error status.
+    HttpStatus(u16),
+    /// The page loaded but had no content.
+    EmptyDocument,
+    /// The technology definitions could not be read or parsed.
+    Definitions(String),
     Fetch(String),
     Analyze(String),
     Other(String),
@@ -69,6 +99,13 @@ impl fmt::Display for WappError {
             f,
             "{}",
             match self {
+                WappError::BrowserLaunch(err) => format!("BrowserLaunch/{}", err),
+                WappError::Dns(err) => format!("Dns/{}", err),
+                WappError::Tls(err) => format!("Tls/{}", err),
+                WappError::Timeout => "Timeout".to_string(),
+                WappError::HttpStatus(status) => format!("HttpStatus/{}", status),
+                WappError::EmptyDocument => "EmptyDocument".to_string(),
+                WappError::Definitions(err) => format!("Definitions/{}", err),
                 WappError::Fetch(err) => format!("Fetch/{}", err),
                 WappError::Analyze(err) => format!("Analyze/{}", err),
                 WappError::Other(err) => format!("Other/{}", err),
@@ -95,125 +132,80 @@ impl From<std::str::Utf8Error> for WappError {
     }
 }
 
-use std::time::{Duration, Instant};
-
-pub async fn scan(url: Url, with_timing: Option<bool>) -> Analysis {
-    let url_str = url.as_str().to_string();
-
-    let start = match with_timing {
-        Some(true) => Some(Instant::now()),
-        _ => None,
-    };
-
-    match fetch(url).await {
-        Some(raw_data) => {
-            let result: HashSet<Tech> =
-                wapp::check(raw_data).await.into_iter().collect();
-
-            Analysis {
-                url: url_str,
-                result: Ok(result),
-                scan_time: start.map(|s| s.elapsed()),
-            }
-        }
-        None => Analysis {
-            url: url_str,
-            result: Err("Error".to_string()),
-            scan_time: start.map(|s| s.elapsed()),
+use std::time::Duration;
+
+/// Scans `url` with a headless Chrome launched for this scan alone, using the
+/// default [`ScanConfig`] when `config` is `None` and the built-in
+/// [`Technologies`]. Create a [`Scanner`] to share one browser across several
+/// scans or to use other definitions.
+pub async fn scan(
+    url: Url,
+    with_timing: Option<bool>,
+    min_confidence: Option<u32>,
+    config: Option<ScanConfig>,
+) -> Analysis {
+    match Scanner::with_config(config.unwrap_or_default()) {
+        Ok(scanner) => scanner.scan(url, with_timing, min_confidence).await,
+        Err(err) => Analysis {
+            url: url.to_string(),
+            result: Err(err),
+            scan_time: None,
         },
     }
 }
 
-
-fn get_html(tab: &Tab) -> Option<String> {
-    let remote_object = tab
-        .evaluate("document.documentElement.outerHTML", false)
-        .ok()?;
-
-    let json = remote_object.value?;
-    let str = json.as_str()?;
-
-    Some(str.to_owned())
-}
-
-async fn fetch(url: Url) -> Option<Arc<wapp::RawData>> {
-    let browser = Browser::new(
-        LaunchOptions::default_builder()
-            .port(Some(8242))
-            .sandbox(false)
-            .build()
-            .unwrap(),
+/// Like [`scan`], with `fetcher` instead of headless Chrome, e.g. an
+/// [`HttpFetcher`] for pages that don't need their scripts run.
+pub async fn scan_with(
+    fetcher: impl Fetcher + 'static,
+    url: Url,
+    with_timing: Option<bool>,
+    min_confidence: Option<u32>,
+    config: Option<ScanConfig>,
+) -> Analysis {
+    Scanner::with_fetcher(
+        config.unwrap_or_default(),
+        Technologies::embedded(),
+        fetcher,
     )
-        .unwrap();
-
-    let tab = browser.wait_for_initial_tab().ok()?;
+    .scan(url, with_timing, min_confidence)
+    .await
+}
 
-    let responses = Arc::new(Mutex::new(Vec::new()));
-    let responses2 = responses.clone();
+/// Detects the technologies in a page fetched by other means, such as a crawler
+/// or an archive, with the built-in [`Technologies`]. The rules run on the
+/// calling thread: there is no browser, and no Tokio runtime is needed. Build
+/// the page data with [`RawData::from_html_and_headers`].
+pub fn analyze(raw_data: &RawData) -> Analysis {
+    analyze_with(&Technologies::embedded(), raw_data, MatchOptions::default())
+}
 
-    tab.enable_response_handling(Box::new(move |response, fetch_body| {
-        let body = fetch_body().unwrap_or(GetResponseBodyReturnObject {
-            body: "".to_string(),
-            base_64_encoded: false,
-        });
-        responses2.lock().unwrap().push((response, body));
-    }))
-        .unwrap();
-    tab.navigate_to(url.as_str()).ok()?;
-
-    let rendered_tab = tab.wait_until_navigated().ok()?;
-
-    let html = get_html(rendered_tab).unwrap();
-
-    let headers: HashMap<String, String> = responses
-        .lock()
-        .unwrap()
-        .first()
-        .and_then(|(response, _)| response.response.headers.0.as_ref())
-        .and_then(|headers| headers.as_object())
-        .map(|headers| {
-            headers
-                .iter()
-                .map(|(a, b)| (a.to_lowercase(), b.to_string().replace("\"", "")))
-                .collect()
-        })?;
-    let cookies: Vec<wapp::Cookie> = tab
-        .get_cookies()
-        .ok()?
-        .into_iter()
-        .map(|c| wapp::Cookie {
-            name: c.name,
-            value: c.value,
-        })
-        .collect();
-    //let cookies: Vec<wapp::Cookie> = vec![wapp::Cookie {name: "a".to_string(), value: "value".to_string()}];
-
-    let parsed_html = Html::parse_fragment(&html);
-    let selector = Selector::parse("meta").unwrap();
-    let script_selector = Selector::parse("script").ok()?;
-    let mut script_tags = vec![];
-    for js in parsed_html.select(&script_selector) {
-        script_tags.push(js.html());
+/// Like [`analyze`], with other definitions and match options. A panic while
+/// checking is reported as [`WappError::Analyze`].
+pub fn analyze_with(
+    technologies: &Technologies,
+    raw_data: &RawData,
+    options: MatchOptions,
+) -> Analysis {
+    let detected = panic::catch_unwind(AssertUnwindSafe(|| {
+        wapp::detect(technologies, raw_data, options)
+    }));
+    Analysis {
+        url: raw_data.url.clone(),
+        result: detected
+            .map(|techs| techs.into_iter().collect())
+            .map_err(|panic| WappError::Analyze(panic_message(panic.as_ref()))),
+        scan_time: None,
     }
+}
 
-    // Note: using a hashmap will not support two meta tags with the same name and different values,
-    // though I'm not sure if that's legal html.
-    let mut meta_tags = HashMap::new();
-    for meta in parsed_html.select(&selector) {
-        if let (Some(name), Some(content)) =
-        (meta.value().attr("name"), meta.value().attr("content"))
-        {
-            // eprintln!("META {} -> {}", name, content);
-            meta_tags.insert(String::from(name), String::from(content));
-        }
+/// The message a panic was raised with, when it has one.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "the rules panicked".to_string(),
+        },
     }
-    let raw_data = Arc::new(RawData {
-        headers,
-        cookies,
-        meta_tags,
-        script_tags,
-        html,
-    });
-
-    Some(raw_data)
 }
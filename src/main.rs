@@ -1,8 +1,312 @@
+use clap::{Parser, ValueEnum};
+use futures::stream::{self, StreamExt};
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
+use wappalyzer::wapp::MatchOptions;
+use wappalyzer::{Analysis, FileFetcher, HttpFetcher, Layer, ScanConfig, Scanner, Technologies};
+
+/// Identifies technologies used on web pages using a headless browser.
+///
+/// URLs are read from the arguments, from --file, or one per line from stdin
+/// when neither is given, unless --har is. Exits with 1 when a scan fails and 2
+/// on bad input.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// URLs to scan.
+    urls: Vec<String>,
+
+    /// Read URLs from this file, one per line.
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+
+    /// How to print each analysis.
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+
+    /// How many pages to scan at once.
+    #[arg(short = 'j', long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Seconds a page may take to scan.
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Only report technologies detected with at least this confidence (0-100).
+    #[arg(long)]
+    min_confidence: Option<u32>,
+
+    /// Only report technologies in this category; repeat for several.
+    #[arg(long = "category")]
+    categories: Vec<String>,
+
+    /// Report how long each scan took.
+    #[arg(long)]
+    timing: bool,
+
+    /// Also match header rules against the redirects that led to each page.
+    #[arg(long)]
+    redirect_headers: bool,
+
+    /// Check pages that answered with an error status instead of reporting
+    /// the status as the error.
+    #[arg(long)]
+    error_pages: bool,
+
+    /// Report the rules that matched each technology, and what they matched.
+    #[arg(long)]
+    evidence: bool,
+
+    /// Read the technology definitions from this apps.json file, or from a
+    /// directory in the upstream layout, instead of the built-in copy.
+    #[arg(long)]
+    technologies: Option<PathBuf>,
+
+    /// Merge the apps of this file over the definitions, replacing or
+    /// disabling apps by name; repeat for several, applied in order.
+    #[arg(long = "layer")]
+    layers: Vec<PathBuf>,
+
+    /// How to load the pages.
+    #[arg(long, value_enum, default_value_t = Backend::Chrome)]
+    fetcher: Backend,
+
+    /// Analyze the page recorded in this HAR archive instead of scanning
+    /// URLs; repeat for several.
+    #[arg(long = "har", conflicts_with_all = ["urls", "file"])]
+    hars: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Render the pages in headless Chrome, running their scripts.
+    Chrome,
+    /// Download the pages over HTTP without running them; much faster.
+    Http,
+    /// Read pages saved to disk, from file:// URLs.
+    File,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// One JSON object per line.
+    Json,
+    /// Indented JSON.
+    Pretty,
+    /// One technology per line, tab separated.
+    Text,
+}
+
+fn read_urls(args: &Args) -> io::Result<Vec<String>> {
+    let mut lines = args.urls.clone();
+    if let Some(file) = &args.file {
+        let file = std::fs::File::open(file)?;
+        for line in BufReader::new(file).lines() {
+            lines.push(line?);
+        }
+    } else if lines.is_empty() {
+        for line in io::stdin().lock().lines() {
+            lines.push(line?);
+        }
+    }
+
+    Ok(lines
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+fn load_technologies(args: &Args) -> Result<Arc<Technologies>, wappalyzer::WappError> {
+    let technologies = match &args.technologies {
+        Some(path) => Arc::new(Technologies::from_path(path)?),
+        None => Technologies::embedded(),
+    };
+    if args.technologies.is_none() && args.layers.is_empty() {
+        return Ok(technologies);
+    }
+
+    let mut technologies = technologies.as_ref().clone();
+    for path in &args.layers {
+        for conflict in technologies.merge(Layer::from_path(path)?) {
+            eprintln!("warning: {}: {}", path.display(), conflict);
+        }
+    }
+    // Patterns that don't compile never match, which is easy to miss in
+    // definitions of our own.
+    for error in technologies.pattern_errors() {
+        eprintln!("warning: {}", error);
+    }
+    Ok(Arc::new(technologies))
+}
+
+fn print(analysis: &Analysis, format: Format) {
+    match format {
+        Format::Json => match serde_json::to_string(analysis) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("{}: {}", analysis.url, err),
+        },
+        Format::Pretty => println!("{:?}", analysis),
+        Format::Text => match &analysis.result {
+            Ok(techs) => {
+                let mut techs: Vec<_> = techs.iter().collect();
+                techs.sort_by(|a, b| a.name.cmp(&b.name));
+                for tech in techs {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        analysis.url,
+                        tech.name,
+                        tech.version.as_deref().unwrap_or("-"),
+                        tech.category,
+                        tech.confidence
+                    );
+                }
+            }
+            Err(err) => println!("{}\terror\t{}", analysis.url, err),
+        },
+    }
+}
+
+/// Keeps only the technologies in any of the categories asked for, if any.
+/// A technology's `category` is only the first of its app's categories, so
+/// all of them are looked up.
+fn retain_categories(analysis: &mut Analysis, categories: &[String], technologies: &Technologies) {
+    if categories.is_empty() {
+        return;
+    }
+    if let Ok(techs) = &mut analysis.result {
+        techs.retain(|tech| {
+            let names: Vec<&str> = match technologies.get(&tech.name) {
+                Some(app) => app
+                    .cats()
+                    .iter()
+                    .filter_map(|id| technologies.category_name(*id))
+                    .collect(),
+                None => vec![tech.category.as_str()],
+            };
+            categories
+                .iter()
+                .any(|category| names.iter().any(|name| category.eq_ignore_ascii_case(name)))
+        });
+    }
+}
+
+/// Analyzes the HAR archives given with --har, offline.
+fn replay(args: &Args) -> ExitCode {
+    let technologies = match load_technologies(args) {
+        Ok(technologies) => technologies,
+        Err(err) => {
+            eprintln!("could not load the technologies: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+    let options = MatchOptions {
+        redirect_headers: args.redirect_headers,
+        evidence: args.evidence,
+    };
+
+    let mut failed = false;
+    for path in &args.hars {
+        let mut analysis =
+            wappalyzer::analyze_har_with(path, &technologies, options, args.error_pages);
+        if let (Ok(techs), Some(min_confidence)) = (&mut analysis.result, args.min_confidence) {
+            techs.retain(|tech| tech.confidence >= min_confidence);
+        }
+        retain_categories(&mut analysis, &args.categories, &technologies);
+        failed |= analysis.result.is_err();
+        print(&analysis, args.format);
+    }
+    warn_exhausted(&technologies);
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Warns about the patterns that ran out of their backtracking budget, which
+/// may have missed technologies.
+fn warn_exhausted(technologies: &Technologies) {
+    for error in technologies.exhausted_patterns() {
+        eprintln!("warning: {}", error);
+    }
+}
 
 #[tokio::main]
-async fn main() {
-    let url = Url::parse(&String::from("https://200.150.197.45:443")).expect("ERR");
-    let res = wappalyzer::scan(url, Option::from(true)).await;
-    println!("{:?}", res);
-}
\ No newline at end of file
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    if !args.hars.is_empty() {
+        return replay(&args);
+    }
+
+    let urls = match read_urls(&args) {
+        Ok(urls) => urls,
+        Err(err) => {
+            eprintln!("could not read the URLs: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+    let urls: Vec<Url> = match urls.iter().map(|url| Url::parse(url)).collect() {
+        Ok(urls) => urls,
+        Err(err) => {
+            eprintln!("invalid URL: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+    if urls.is_empty() {
+        eprintln!("no URLs to scan");
+        return ExitCode::from(2);
+    }
+
+    let technologies = match load_technologies(&args) {
+        Ok(technologies) => technologies,
+        Err(err) => {
+            eprintln!("could not load the technologies: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+
+    let config = ScanConfig {
+        navigation_timeout: Duration::from_secs(args.timeout),
+        scan_timeout: Duration::from_secs(args.timeout),
+        concurrency: args.concurrency,
+        redirect_headers: args.redirect_headers,
+        error_pages: args.error_pages,
+        evidence: args.evidence,
+        ..ScanConfig::default()
+    };
+    let scanner = match args.fetcher {
+        Backend::Chrome => Scanner::with_technologies(config, technologies),
+        Backend::Http => HttpFetcher::new(&config)
+            .map(|fetcher| Scanner::with_fetcher(config, technologies, fetcher)),
+        Backend::File => Ok(Scanner::with_fetcher(config, technologies, FileFetcher)),
+    };
+    let scanner = match scanner {
+        Ok(scanner) => scanner,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut scans = scanner.scan_many(stream::iter(urls), Some(args.timing), args.min_confidence);
+
+    let mut failed = false;
+    while let Some(mut analysis) = scans.next().await {
+        retain_categories(&mut analysis, &args.categories, scanner.technologies());
+        failed |= analysis.result.is_err();
+        print(&analysis, args.format);
+    }
+    warn_exhausted(scanner.technologies());
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,485 @@
+//! Compiled apps.json patterns.
+//!
+//! Every pattern is compiled once, when the apps.json data is loaded, and keeps
+//! the tags that followed it so matching and version inference don't need to
+//! parse the raw string again.
+//!
+//! The patterns are written for JavaScript's regex engine. Syntax that has a
+//! Rust equivalent, such as `[^]` or a literal `{`, is translated, and patterns
+//! that need look-around or backreferences fall back to the backtracking
+//! `fancy-regex` engine. Backtracking can take exponential time on some input,
+//! so that engine only gets a budget of backtracking steps per match, which
+//! grows with the length of the input up to a cap. A match that runs out of it
+//! counts as no match, and the pattern keeps count so it can be reported.
+
+use regex::Regex;
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::heuristics::{split_pattern, PatternTags};
+
+/// How many backtracking steps `fancy-regex` may take per byte of input.
+const BACKTRACK_STEPS_PER_BYTE: usize = 64;
+
+/// The budgets a backtracking regex is built with, smallest first. A match
+/// gets the smallest that covers its input at [`BACKTRACK_STEPS_PER_BYTE`], or
+/// the largest.
+const BACKTRACK_LIMITS: [usize; 3] = [1_000_000, 10_000_000, 100_000_000];
+
+/// A pattern from apps.json with its regex compiled and its tags parsed.
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    engine: Option<Engine>,
+    tags: PatternTags,
+    error: Option<String>,
+}
+
+#[derive(Clone)]
+enum Engine {
+    Regex(Regex),
+    Backtracking(Backtracking),
+}
+
+/// A regex on the backtracking engine, built once for each of the
+/// [`BACKTRACK_LIMITS`].
+#[derive(Clone)]
+struct Backtracking {
+    regexes: Vec<(usize, fancy_regex::Regex)>,
+    /// How many matches ran out of their budget.
+    exhausted: Arc<AtomicUsize>,
+}
+
+impl Backtracking {
+    fn new(regex: &str) -> Result<Backtracking, String> {
+        let regexes = BACKTRACK_LIMITS
+            .iter()
+            .map(|&limit| {
+                fancy_regex::RegexBuilder::new(regex)
+                    .backtrack_limit(limit)
+                    .build()
+                    .map(|regex| (limit, regex))
+                    .map_err(|err| err.to_string())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Backtracking {
+            regexes,
+            exhausted: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// The regex with the budget for `text`.
+    fn regex(&self, text: &str) -> &fancy_regex::Regex {
+        let steps = text.len().saturating_mul(BACKTRACK_STEPS_PER_BYTE);
+        let (_, regex) = self
+            .regexes
+            .iter()
+            .find(|(limit, _)| *limit >= steps)
+            .or(self.regexes.last())
+            .expect("a regex for every budget");
+        regex
+    }
+
+    /// Counts a match that failed, which can only be by running out of its
+    /// budget, as no match.
+    fn exhausted<T>(&self, result: Result<Option<T>, fancy_regex::Error>) -> Option<T> {
+        result.unwrap_or_else(|_| {
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+            None
+        })
+    }
+}
+
+impl Engine {
+    fn find<'t>(&self, text: &'t str) -> Option<&'t str> {
+        match self {
+            Engine::Regex(regex) => regex.find(text).map(|m| m.as_str()),
+            Engine::Backtracking(backtracking) => backtracking
+                .exhausted(backtracking.regex(text).find(text))
+                .map(|m| m.as_str()),
+        }
+    }
+
+    fn groups<'t>(&self, text: &'t str) -> Option<Vec<Option<&'t str>>> {
+        match self {
+            Engine::Regex(regex) => {
+                let captures = regex.captures(text)?;
+                Some(captures.iter().map(|m| m.map(|m| m.as_str())).collect())
+            }
+            Engine::Backtracking(backtracking) => {
+                let captures = backtracking.exhausted(backtracking.regex(text).captures(text))?;
+                Some(captures.iter().map(|m| m.map(|m| m.as_str())).collect())
+            }
+        }
+    }
+}
+
+impl Pattern {
+    /// Compiles a raw apps.json pattern, e.g. `^WordPress ?([\d.]+)?\;version:\1`.
+    ///
+    /// A regex that can't be compiled leaves a pattern that never matches, and
+    /// [`Pattern::error`] says why.
+    pub fn new(source: &str) -> Pattern {
+        let (regex, tags) = split_pattern(source);
+        let (engine, error) = match compile(regex) {
+            Ok(engine) => (Some(engine), None),
+            Err(error) => (None, Some(error)),
+        };
+        Pattern {
+            source: source.to_string(),
+            engine,
+            tags,
+            error,
+        }
+    }
+
+    /// Why the regex didn't compile, if it didn't.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// The pattern as written in apps.json, tags included.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The compiled regex, unless the pattern needed the backtracking engine
+    /// or didn't compile.
+    pub fn regex(&self) -> Option<&Regex> {
+        match &self.engine {
+            Some(Engine::Regex(regex)) => Some(regex),
+            _ => None,
+        }
+    }
+
+    /// Whether the pattern runs on the backtracking engine.
+    pub fn is_backtracking(&self) -> bool {
+        matches!(self.engine, Some(Engine::Backtracking(_)))
+    }
+
+    /// How many matches ran out of their backtracking budget so far, each of
+    /// which counted as no match.
+    pub fn budget_exhausted(&self) -> usize {
+        match &self.engine {
+            Some(Engine::Backtracking(backtracking)) => {
+                backtracking.exhausted.load(Ordering::Relaxed)
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn tags(&self) -> &PatternTags {
+        &self.tags
+    }
+
+    /// The `confidence:` tag, which defaults to 100.
+    pub fn confidence(&self) -> u32 {
+        self.tags.confidence.unwrap_or(100)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// The part of `text` the pattern matched.
+    pub fn find<'t>(&self, text: &'t str) -> Option<&'t str> {
+        self.engine.as_ref()?.find(text)
+    }
+
+    /// The text of each capture group of the first match, the whole match
+    /// being group 0.
+    pub fn groups<'t>(&self, text: &'t str) -> Option<Vec<Option<&'t str>>> {
+        self.engine.as_ref()?.groups(text)
+    }
+
+    /// The version the `version:` tag resolves to against `text`, if any.
+    pub fn version(&self, text: &str) -> Option<String> {
+        let template = self.tags.version.as_ref()?;
+        template.resolve(&self.groups(text)?)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+        Ok(Pattern::new(&source))
+    }
+}
+
+/// Compiles with the `regex` crate when it can, which guarantees linear time,
+/// and with the backtracking engine otherwise.
+fn compile(regex: &str) -> Result<Engine, String> {
+    if let Ok(compiled) = Regex::new(&format!("(?i){}", regex)) {
+        return Ok(Engine::Regex(compiled));
+    }
+    let translated = translate_js(regex);
+    let regex = translated.as_deref().unwrap_or(regex);
+    if let Ok(compiled) = Regex::new(&format!("(?i){}", regex)) {
+        return Ok(Engine::Regex(compiled));
+    }
+    Backtracking::new(&format!("(?i){}", regex))
+        .map(Engine::Backtracking)
+        .map_err(|err| match js_only_construct(regex) {
+            Some(construct) => format!(
+                "uses {} in a way only JavaScript supports: {}",
+                construct, err
+            ),
+            None => err,
+        })
+}
+
+/// The first JavaScript regex construct in `regex` that the `regex` crate
+/// doesn't support, to explain why a pattern didn't compile.
+fn js_only_construct(regex: &str) -> Option<&'static str> {
+    let mut in_class = false;
+    let mut chars = regex.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, '1'..='9')) = chars.next() {
+                    if !in_class {
+                        return Some("backreferences");
+                    }
+                }
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class => {
+                let constructs = [
+                    ("(?=", "look-ahead"),
+                    ("(?!", "negative look-ahead"),
+                    ("(?<=", "look-behind"),
+                    ("(?<!", "negative look-behind"),
+                ];
+                for (prefix, construct) in constructs {
+                    if regex[i..].starts_with(prefix) {
+                        return Some(construct);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Rewrites JavaScript regex syntax the `regex` crate reads differently:
+/// `[^]` matches any character, a `{` that doesn't start a repetition and a
+/// `[` inside a class are literals. Returns `None` when nothing needed
+/// rewriting.
+fn translate_js(regex: &str) -> Option<String> {
+    let mut translated = String::with_capacity(regex.len());
+    let mut in_class = false;
+    let mut chars = regex.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                translated.push(c);
+                if let Some((_, escaped)) = chars.next() {
+                    translated.push(escaped);
+                }
+            }
+            '[' if in_class => translated.push_str("\\["),
+            '[' if regex[i..].starts_with("[^]") => {
+                translated.push_str("[\\s\\S]");
+                chars.nth(1);
+            }
+            '[' => {
+                in_class = true;
+                translated.push(c);
+                // A `]` straight after the opening bracket is part of the class.
+                if regex[i + 1..].starts_with(']') {
+                    translated.push_str("\\]");
+                    chars.next();
+                }
+            }
+            ']' if in_class => {
+                in_class = false;
+                translated.push(c);
+            }
+            '{' if !in_class && !is_repetition(&regex[i..]) => translated.push_str("\\{"),
+            _ => translated.push(c),
+        }
+    }
+
+    if translated == regex {
+        None
+    } else {
+        Some(translated)
+    }
+}
+
+/// Whether `text` starts with a repetition such as `{2}`, `{2,}` or `{2,5}`.
+fn is_repetition(text: &str) -> bool {
+    let end = match text.find('}') {
+        Some(end) => end,
+        None => return false,
+    };
+    let (min, max) = match text[1..end].split_once(',') {
+        Some((min, max)) => (min, max),
+        None => (&text[1..end], "0"),
+    };
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    !min.is_empty() && digits(min) && digits(max)
+}
+
+/// Deserializes a single value or a list of values, e.g. a pattern or a list
+/// of patterns.
+pub fn one_or_more<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMore<T> {
+        One(T),
+        More(Vec<T>),
+    }
+
+    match OneOrMore::deserialize(deserializer) {
+        Ok(OneOrMore::One(value)) => Ok(vec![value]),
+        Ok(OneOrMore::More(values)) => Ok(values),
+        Err(_) => Err(de::Error::custom("expected a value or a list of values")),
+    }
+}
+
+/// Deserializes a map whose values are each a single value or a list of
+/// values, e.g. the `meta` rules of the split upstream format.
+pub fn map_of_one_or_more<'de, D, T>(deserializer: D) -> Result<HashMap<String, Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(transparent, bound = "T: Deserialize<'de>")]
+    struct OneOrMore<T>(#[serde(deserialize_with = "one_or_more")] Vec<T>);
+
+    let map = HashMap::<String, OneOrMore<T>>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(key, values)| (key, values.0))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let pattern = Pattern::new("^WordPress ?([\\d.]+)?\\;version:\\1\\;confidence:50");
+        assert!(pattern.is_match("wordpress 5.2"));
+        assert!(!pattern.is_match("Drupal 8"));
+        assert_eq!(pattern.version("WordPress 5.2"), Some("5.2".to_string()));
+        assert_eq!(pattern.tags().confidence, Some(50));
+
+        let invalid = Pattern::new("(foo");
+        assert!(invalid.regex().is_none());
+        assert!(!invalid.is_match("(foo"));
+        assert!(invalid.error().is_some());
+    }
+
+    #[test]
+    fn test_backtracking() {
+        let lookahead = Pattern::new("^https?://(?!o\\.)\\w+\\.advg\\.jp/");
+        assert!(lookahead.is_backtracking());
+        assert!(lookahead.is_match("https://www.advg.jp/ad.js"));
+        assert!(!lookahead.is_match("https://o.advg.jp/ad.js"));
+
+        let backreference = Pattern::new("<(\\w+)>v([\\d.]+)</\\1>\\;version:\\2");
+        assert_eq!(
+            backreference.version("<b>v1.2</b>"),
+            Some("1.2".to_string())
+        );
+        assert_eq!(backreference.version("<b>v1.2</i>"), None);
+
+        // Exponential backtracking runs out of steps instead of hanging, and
+        // is counted.
+        let pathological = Pattern::new("^(a+)+\\1c");
+        assert!(pathological.is_backtracking());
+        assert_eq!(pathological.budget_exhausted(), 0);
+        assert!(!pathological.is_match(&"a".repeat(40)));
+        assert_eq!(pathological.budget_exhausted(), 1);
+        assert!(pathological.is_match("aac"));
+        assert_eq!(lookahead.budget_exhausted(), 0);
+    }
+
+    #[test]
+    fn test_js_only_construct() {
+        assert_eq!(js_only_construct("a(?=b)"), Some("look-ahead"));
+        assert_eq!(js_only_construct("(?<!x)y"), Some("negative look-behind"));
+        assert_eq!(js_only_construct("(a)\\1"), Some("backreferences"));
+        assert_eq!(js_only_construct("[(?=]\\d"), None);
+    }
+
+    #[test]
+    fn test_translate_js() {
+        assert_eq!(
+            translate_js("<footer>[^]{1,5}</footer>"),
+            Some("<footer>[\\s\\S]{1,5}</footer>".to_string())
+        );
+        assert_eq!(
+            translate_js("config\\('x', {\\s+release: '([0-9.]+)'"),
+            Some("config\\('x', \\{\\s+release: '([0-9.]+)'".to_string())
+        );
+        assert_eq!(translate_js("[[a]"), Some("[\\[a]".to_string()));
+        assert_eq!(translate_js("a{2,}[{]\\{"), None);
+
+        let sentry =
+            Pattern::new("Raven\\.config\\('[^']*', {\\s+release: '([0-9\\.]+)'\\;version:\\1");
+        assert!(sentry.error().is_none());
+        assert_eq!(
+            sentry.version("Raven.config('abc', {\n release: '1.2.3'"),
+            Some("1.2.3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let patterns: Vec<Pattern> = serde_json::from_str("[\"foo\", \"bar\"]").unwrap();
+        assert_eq!(patterns.len(), 2);
+        let pattern: Pattern = serde_json::from_str("\"foo\\\\;confidence:25\"").unwrap();
+        assert_eq!(pattern.source(), "foo\\;confidence:25");
+        assert_eq!(pattern.tags().confidence, Some(25));
+
+        #[derive(Deserialize)]
+        struct Meta {
+            #[serde(deserialize_with = "map_of_one_or_more")]
+            meta: HashMap<String, Vec<Pattern>>,
+        }
+        let meta: Meta =
+            serde_json::from_str("{\"meta\": {\"a\": \"foo\", \"b\": [\"bar\", \"baz\"]}}")
+                .unwrap();
+        assert_eq!(meta.meta["a"].len(), 1);
+        assert_eq!(meta.meta["b"].len(), 2);
+    }
+}
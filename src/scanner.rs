@@ -0,0 +1,199 @@
+//! Scanning pages with a fetcher that is shared across scans.
+
+use futures::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::chrome::ChromeFetcher;
+use crate::fetcher::Fetcher;
+use crate::wapp::{self, MatchOptions, Tech};
+use crate::{Analysis, Technologies, WappError};
+
+/// How to launch Chrome and load pages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanConfig {
+    /// The Chrome or Chromium binary; detected automatically when `None`.
+    pub chrome_path: Option<PathBuf>,
+    pub headless: bool,
+    pub window_size: Option<(u32, u32)>,
+    /// Sent instead of the browser's or the HTTP client's own user agent when
+    /// set.
+    pub user_agent: Option<String>,
+    /// Extra command-line arguments for Chrome, e.g. `--proxy-server=...`.
+    pub args: Vec<String>,
+    /// How long the browser may go without sending any events before it is
+    /// considered gone. This also bounds each call to the browser, and a
+    /// [`ChromeFetcher`] that sat idle for longer relaunches its browsers on
+    /// the next fetch.
+    pub idle_timeout: Duration,
+    /// How long a page may take to load.
+    pub navigation_timeout: Duration,
+    /// Chrome's sandbox usually has to be off when running as root or in a
+    /// container, so it is off by default.
+    pub sandbox: bool,
+    /// How many browsers a [`ChromeFetcher`] launches; fetches take turns
+    /// between them.
+    pub browsers: usize,
+    /// How many pages [`Scanner::scan_many`] scans at once.
+    pub concurrency: usize,
+    /// How long a whole scan may take before it is given up as a timeout.
+    pub scan_timeout: Duration,
+    /// Also match header rules against the redirects that led to the page,
+    /// e.g. to catch the server of a redirecting domain.
+    pub redirect_headers: bool,
+    /// Check pages that answered with an error status like any other, instead
+    /// of reporting [`WappError::HttpStatus`].
+    pub error_pages: bool,
+    /// Report the rules that matched in each technology's `evidence`.
+    pub evidence: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            chrome_path: None,
+            headless: true,
+            window_size: None,
+            user_agent: None,
+            args: vec![],
+            idle_timeout: Duration::from_secs(30),
+            navigation_timeout: Duration::from_secs(20),
+            sandbox: false,
+            browsers: 1,
+            concurrency: 4,
+            scan_timeout: Duration::from_secs(60),
+            redirect_headers: false,
+            error_pages: false,
+            evidence: false,
+        }
+    }
+}
+
+/// Scans pages with a [`Fetcher`], headless Chrome unless told otherwise, and
+/// checks them against the technologies. The fetcher is set up once however
+/// many pages are scanned.
+pub struct Scanner {
+    fetcher: Box<dyn Fetcher>,
+    config: ScanConfig,
+    technologies: Arc<Technologies>,
+}
+
+impl Scanner {
+    /// Launches the browser with the default [`ScanConfig`].
+    pub fn new() -> Result<Scanner, WappError> {
+        Scanner::with_config(ScanConfig::default())
+    }
+
+    /// Launches the browsers, which check pages against the built-in
+    /// [`Technologies`].
+    pub fn with_config(config: ScanConfig) -> Result<Scanner, WappError> {
+        Scanner::with_technologies(config, Technologies::embedded())
+    }
+
+    /// Launches the browsers, which check pages against `technologies`.
+    pub fn with_technologies(
+        config: ScanConfig,
+        technologies: Arc<Technologies>,
+    ) -> Result<Scanner, WappError> {
+        let fetcher = ChromeFetcher::new(&config)?;
+        Ok(Scanner::with_fetcher(config, technologies, fetcher))
+    }
+
+    /// Scans with `fetcher` instead of headless Chrome, e.g. an
+    /// [`HttpFetcher`](crate::HttpFetcher) for fast scans of pages that don't
+    /// need their scripts run.
+    pub fn with_fetcher(
+        config: ScanConfig,
+        technologies: Arc<Technologies>,
+        fetcher: impl Fetcher + 'static,
+    ) -> Scanner {
+        Scanner {
+            fetcher: Box::new(fetcher),
+            config,
+            technologies,
+        }
+    }
+
+    /// The definitions pages are checked against.
+    pub fn technologies(&self) -> &Arc<Technologies> {
+        &self.technologies
+    }
+
+    /// Scans `url`, keeping only the technologies detected with at least
+    /// `min_confidence` (0 to 100, all of them when `None`).
+    pub async fn scan(
+        &self,
+        url: Url,
+        with_timing: Option<bool>,
+        min_confidence: Option<u32>,
+    ) -> Analysis {
+        let url_str = url.as_str().to_string();
+
+        let start = match with_timing {
+            Some(true) => Some(Instant::now()),
+            _ => None,
+        };
+
+        let fetch = self.fetcher.fetch(&url, &self.technologies);
+        let raw_data = match tokio::time::timeout(self.config.scan_timeout, fetch).await {
+            Ok(Ok(raw_data)) => match raw_data.status {
+                Some(status) if status >= 400 && !self.config.error_pages => {
+                    Err(WappError::HttpStatus(status))
+                }
+                _ => Ok(raw_data),
+            },
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(WappError::Timeout),
+        };
+        match raw_data {
+            Ok(raw_data) => {
+                let min_confidence = min_confidence.unwrap_or(0);
+                let options = MatchOptions {
+                    redirect_headers: self.config.redirect_headers,
+                    evidence: self.config.evidence,
+                };
+                let result: Result<HashSet<Tech>, WappError> =
+                    wapp::check(self.technologies.clone(), Arc::new(raw_data), options)
+                        .await
+                        .map(|techs| {
+                            techs
+                                .into_iter()
+                                .filter(|tech| tech.confidence >= min_confidence)
+                                .collect()
+                        });
+
+                Analysis {
+                    url: url_str,
+                    result,
+                    scan_time: start.map(|s| s.elapsed()),
+                }
+            }
+            Err(err) => Analysis {
+                url: url_str,
+                result: Err(err),
+                scan_time: start.map(|s| s.elapsed()),
+            },
+        }
+    }
+
+    /// Scans every URL from `urls`, at most [`ScanConfig::concurrency`] at a
+    /// time, and yields each analysis as soon as it is done, so the results
+    /// don't come in the order of `urls`. Each scan is cut off after
+    /// [`ScanConfig::scan_timeout`], so a page that hangs can't hold up the rest.
+    /// Iterators can be passed in with [`futures::stream::iter`].
+    pub fn scan_many<'a, S>(
+        &'a self,
+        urls: S,
+        with_timing: Option<bool>,
+        min_confidence: Option<u32>,
+    ) -> impl Stream<Item = Analysis> + 'a
+    where
+        S: Stream<Item = Url> + 'a,
+    {
+        urls.map(move |url| self.scan(url, with_timing, min_confidence))
+            .buffer_unordered(self.config.concurrency.max(1))
+    }
+}
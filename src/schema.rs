@@ -0,0 +1,175 @@
+//! Checks apps.json files against the schema.json that ships with them.
+//!
+//! Only the parts of JSON Schema (draft 3) that schema.json uses are
+//! supported: `type`, `properties` with `required`, `patternProperties`,
+//! `additionalProperties` and `items`.
+
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::WappError;
+
+const SCHEMA_TEXT: &str = include_str!("../schema.json");
+
+/// A place where a document doesn't follow the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// Where in the document, as a JSON pointer, e.g. `/apps/WordPress/cats`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+/// Checks the text of an apps.json file against schema.json. Fails only when
+/// the text isn't JSON at all.
+pub fn validate_schema(text: &str) -> Result<Vec<SchemaError>, WappError> {
+    let schema: Value =
+        serde_json::from_str(SCHEMA_TEXT).map_err(|err| WappError::Other(err.to_string()))?;
+    let document: Value =
+        serde_json::from_str(text).map_err(|err| WappError::Definitions(err.to_string()))?;
+
+    let mut errors = vec![];
+    check(&schema, &document, "", &mut errors);
+    Ok(errors)
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<SchemaError>) {
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+        errors.push(SchemaError {
+            path: path.to_string(),
+            message: format!("expected {}", types.join(" or ")),
+        });
+        return;
+    }
+
+    match value {
+        Value::Object(object) => check_object(schema, object, path, errors),
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{}/{}", path, i), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_object(
+    schema: &Value,
+    object: &Map<String, Value>,
+    path: &str,
+    errors: &mut Vec<SchemaError>,
+) {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let pattern_properties: Vec<(Regex, &Value)> = schema
+        .get("patternProperties")
+        .and_then(Value::as_object)
+        .map(|patterns| {
+            patterns
+                .iter()
+                .filter_map(|(pattern, schema)| Some((Regex::new(pattern).ok()?, schema)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // In draft 3, `required` is set on the property's own schema.
+    for (name, property) in properties.into_iter().flatten() {
+        if property.get("required") == Some(&Value::Bool(true)) && !object.contains_key(name) {
+            errors.push(SchemaError {
+                path: path.to_string(),
+                message: format!("missing required property {:?}", name),
+            });
+        }
+    }
+
+    for (name, value) in object {
+        let child = format!("{}/{}", path, name.replace('~', "~0").replace('/', "~1"));
+        let mut described = false;
+        if let Some(property) = properties.and_then(|properties| properties.get(name)) {
+            described = true;
+            check(property, value, &child, errors);
+        }
+        for (regex, property) in &pattern_properties {
+            if regex.is_match(name) {
+                described = true;
+                check(property, value, &child, errors);
+            }
+        }
+        if !described {
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => errors.push(SchemaError {
+                    path: child,
+                    message: "unexpected property".to_string(),
+                }),
+                Some(additional @ Value::Object(_)) => check(additional, value, &child, errors),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apps_json_follows_schema() {
+        let errors = validate_schema(include_str!("../apps.json")).unwrap();
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let errors = validate_schema(
+            r#"{
+                "apps": {
+                    "Foo": {"website": "", "url": ["a", "b"], "color": "red"},
+                    "Bar": {"cats": ["1"], "website": ""}
+                },
+                "categories": {"one": {"name": "CMS"}}
+            }"#,
+        )
+        .unwrap();
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "/apps/Bar/cats/0: expected number",
+                "/apps/Foo: missing required property \"cats\"",
+                "/apps/Foo/color: unexpected property",
+                "/apps/Foo/url: expected string",
+                "/categories/one: unexpected property",
+            ]
+        );
+    }
+}
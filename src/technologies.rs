@@ -0,0 +1,757 @@
+//! The technology definitions pages are checked against.
+//!
+//! Definitions are read at runtime, so fingerprint updates don't need a
+//! rebuild, either from a single apps.json file or from the split layout
+//! upstream Wappalyzer uses now: a `technologies` directory of JSON files with
+//! `categories.json` and `groups.json` next to it. A copy of apps.json is built
+//! into the binary and serves as the default.
+//!
+//! Further definitions can be layered over a database with [`Layer`]s, to add
+//! apps of our own, replace apps by name or disable them.
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::wapp::App;
+use crate::WappError;
+
+// The build.rs build script reads the local apps.json file at build time
+// and includes the text string as a constant in a created 'apps.json.rs'
+// in the build dir. Here, we include this constant.
+include!(concat!(env!("OUT_DIR"), "/apps.json.rs"));
+
+lazy_static! {
+    static ref EMBEDDED: Arc<Technologies> = Arc::new(
+        Technologies::from_json(APPS_JSON_TEXT).expect("Error loading the apps.json file")
+    );
+}
+
+/// The layout of apps.json. The split layout is read into the same shape.
+#[derive(Deserialize)]
+struct AppsJsonData {
+    apps: HashMap<String, App>,
+    categories: HashMap<u32, Category>,
+    #[serde(default)]
+    groups: HashMap<u32, Group>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Category {
+    name: String,
+    #[serde(default)]
+    groups: Vec<u32>,
+}
+
+/// A group of categories, e.g. `Security`.
+#[derive(Debug, Clone, Deserialize)]
+struct Group {
+    name: String,
+}
+
+/// A database of technologies and their categories, loaded from apps.json.
+#[derive(Debug, Clone)]
+pub struct Technologies {
+    apps: HashMap<String, App>,
+    categories: HashMap<u32, Category>,
+    groups: HashMap<u32, Group>,
+    /// Apps a layer disabled. References to them aren't conflicts.
+    disabled: HashSet<String>,
+}
+
+/// Definitions to merge over a [`Technologies`] database. A layer file looks
+/// like apps.json, but every key is optional and `disable` lists the apps to
+/// remove:
+///
+/// ```json
+/// {
+///     "apps": {"Intranet": {"cats": [200], "website": "", "html": "intranet-v2"}},
+///     "categories": {"200": {"name": "Internal"}},
+///     "disable": ["Google Font API"]
+/// }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Layer {
+    #[serde(default)]
+    apps: HashMap<String, App>,
+    #[serde(default)]
+    categories: HashMap<u32, Category>,
+    #[serde(default)]
+    groups: HashMap<u32, Group>,
+    #[serde(default)]
+    disable: Vec<String>,
+}
+
+impl Layer {
+    pub fn from_json(text: &str) -> Result<Layer, WappError> {
+        serde_json::from_str(text).map_err(definitions_error)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Layer, WappError> {
+        serde_json::from_reader(reader).map_err(definitions_error)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Layer, WappError> {
+        read_json(path.as_ref())
+    }
+}
+
+/// A problem with the definitions that doesn't stop them from loading.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Conflict {
+    /// An app is listed under, or requires, a category that isn't defined.
+    UnknownCategory { app: String, category: u32 },
+    /// An app implies, excludes or requires an app that isn't defined.
+    DanglingReference {
+        app: String,
+        field: &'static str,
+        target: String,
+    },
+    /// A layer disables an app that isn't defined.
+    UnknownDisabled(String),
+    /// A layer replaces an app, a category or a group that was defined, by
+    /// name or ID.
+    Overridden { kind: &'static str, name: String },
+}
+
+/// A pattern that never matches, and why.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PatternError {
+    pub app: String,
+    /// The field the pattern is in, e.g. `html` or `meta.generator`.
+    pub field: String,
+    pub pattern: String,
+    pub reason: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {:?} {}",
+            self.app, self.field, self.pattern, self.reason
+        )
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::UnknownCategory { app, category } => {
+                write!(f, "{}: unknown category {}", app, category)
+            }
+            Conflict::DanglingReference { app, field, target } => {
+                write!(f, "{}: {} unknown app {}", app, field, target)
+            }
+            Conflict::UnknownDisabled(app) => write!(f, "cannot disable unknown app {}", app),
+            Conflict::Overridden { kind, name } => write!(f, "{} {} overridden", kind, name),
+        }
+    }
+}
+
+impl Technologies {
+    /// The copy of apps.json built into the binary. It is parsed once and
+    /// shared.
+    pub fn embedded() -> Arc<Technologies> {
+        EMBEDDED.clone()
+    }
+
+    /// Parses the text of an apps.json file.
+    pub fn from_json(text: &str) -> Result<Technologies, WappError> {
+        let data: AppsJsonData = serde_json::from_str(text).map_err(definitions_error)?;
+        Ok(Technologies::new(data))
+    }
+
+    /// Reads an apps.json file from `reader`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Technologies, WappError> {
+        let data: AppsJsonData = serde_json::from_reader(reader).map_err(definitions_error)?;
+        Ok(Technologies::new(data))
+    }
+
+    /// Reads the apps.json file at `path`, or the split layout when `path` is
+    /// a directory.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Technologies, WappError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Technologies::from_dir(path);
+        }
+        let file = std::fs::File::open(path).map_err(|err| io_error(path, err))?;
+        Technologies::from_reader(std::io::BufReader::new(file)).map_err(|err| in_file(path, err))
+    }
+
+    /// Reads the split layout from `dir`: every JSON file in
+    /// `dir/technologies`, `dir/categories.json` and, if there is one,
+    /// `dir/groups.json`. An app defined in several files keeps the definition
+    /// from the last file by name.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Technologies, WappError> {
+        let dir = dir.as_ref();
+        let technologies_dir = dir.join("technologies");
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&technologies_dir)
+            .map_err(|err| io_error(&technologies_dir, err))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+
+        let mut apps = HashMap::new();
+        for file in files {
+            apps.extend(read_json::<HashMap<String, App>>(&file)?);
+        }
+        let categories = read_json(&dir.join("categories.json"))?;
+        let groups_file = dir.join("groups.json");
+        let groups = if groups_file.exists() {
+            read_json(&groups_file)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Technologies::new(AppsJsonData {
+            apps,
+            categories,
+            groups,
+        }))
+    }
+
+    fn new(data: AppsJsonData) -> Technologies {
+        let mut technologies = Technologies {
+            apps: data.apps,
+            categories: data.categories,
+            groups: data.groups,
+            disabled: HashSet::new(),
+        };
+        technologies.resolve_names();
+        technologies
+    }
+
+    /// Fills in the names the apps are known by and their category names.
+    fn resolve_names(&mut self) {
+        for (app_name, app) in self.apps.iter_mut() {
+            app.name = String::from(app_name);
+            // The first category an app is listed under is the one reported.
+            app.category = app
+                .cats
+                .iter()
+                .find_map(|id| self.categories.get(id))
+                .map(|category| category.name.clone())
+                .unwrap_or_default();
+        }
+    }
+
+    /// Merges `layer` over these definitions. Its categories and groups are
+    /// added or replace those with the same ID, its apps are added or replace
+    /// those with the same name, and then the apps it disables are removed.
+    ///
+    /// Returns the conflicts the merge brought in, starting with what it
+    /// replaced; those the definitions already had are left out.
+    pub fn merge(&mut self, layer: Layer) -> Vec<Conflict> {
+        let before: HashSet<Conflict> = self.validate().into_iter().collect();
+
+        let overridden = |kind, name: String| Conflict::Overridden { kind, name };
+        let mut conflicts: Vec<Conflict> = layer
+            .apps
+            .keys()
+            .filter(|name| self.apps.contains_key(*name))
+            .map(|name| overridden("app", name.clone()))
+            .collect();
+        conflicts.extend(
+            layer
+                .categories
+                .keys()
+                .filter(|id| self.categories.contains_key(id))
+                .map(|id| overridden("category", id.to_string())),
+        );
+        conflicts.extend(
+            layer
+                .groups
+                .keys()
+                .filter(|id| self.groups.contains_key(id))
+                .map(|id| overridden("group", id.to_string())),
+        );
+        conflicts.sort();
+
+        self.categories.extend(layer.categories);
+        self.groups.extend(layer.groups);
+        for name in layer.apps.keys() {
+            self.disabled.remove(name);
+        }
+        self.apps.extend(layer.apps);
+
+        for name in layer.disable {
+            if self.apps.remove(&name).is_none() && !self.disabled.contains(&name) {
+                conflicts.push(Conflict::UnknownDisabled(name.clone()));
+            }
+            self.disabled.insert(name);
+        }
+        self.resolve_names();
+
+        conflicts.extend(
+            self.validate()
+                .into_iter()
+                .filter(|conflict| !before.contains(conflict)),
+        );
+        conflicts
+    }
+
+    /// Every pattern that doesn't compile, and so never matches, and the
+    /// `dom` rules whose selector isn't valid.
+    pub fn pattern_errors(&self) -> Vec<PatternError> {
+        let mut errors: Vec<PatternError> = self
+            .apps
+            .values()
+            .flat_map(|app| {
+                app.patterns()
+                    .into_iter()
+                    .filter_map(move |(field, pattern)| {
+                        Some(PatternError {
+                            app: app.name().to_string(),
+                            field,
+                            pattern: pattern.source().to_string(),
+                            reason: pattern.error()?.to_string(),
+                        })
+                    })
+            })
+            .collect();
+        for app in self.apps.values() {
+            for (selector, rule) in app.dom() {
+                if let Some(error) = rule.error() {
+                    errors.push(PatternError {
+                        app: app.name().to_string(),
+                        field: format!("dom.{}", selector),
+                        pattern: selector.clone(),
+                        reason: error.to_string(),
+                    });
+                }
+            }
+        }
+        errors.sort();
+        errors
+    }
+
+    /// Every pattern of the rule types that scans don't check, which never
+    /// match either. The upstream definitions have many of them.
+    pub fn unchecked_rules(&self) -> Vec<PatternError> {
+        let mut rules: Vec<PatternError> = self
+            .apps
+            .values()
+            .flat_map(|app| {
+                app.patterns()
+                    .into_iter()
+                    .filter_map(move |(field, pattern)| {
+                        Some(PatternError {
+                            app: app.name().to_string(),
+                            reason: unchecked(&field)?,
+                            field,
+                            pattern: pattern.source().to_string(),
+                        })
+                    })
+            })
+            .collect();
+        rules.sort();
+        rules
+    }
+
+    /// Every pattern that ran out of its backtracking budget on some page
+    /// checked so far, where it counted as no match.
+    pub fn exhausted_patterns(&self) -> Vec<PatternError> {
+        let mut errors: Vec<PatternError> = self
+            .apps
+            .values()
+            .flat_map(|app| {
+                app.patterns()
+                    .into_iter()
+                    .filter(|(_, pattern)| pattern.budget_exhausted() > 0)
+                    .map(move |(field, pattern)| PatternError {
+                        app: app.name().to_string(),
+                        field,
+                        pattern: pattern.source().to_string(),
+                        reason: format!(
+                            "ran out of its backtracking budget {} times",
+                            pattern.budget_exhausted()
+                        ),
+                    })
+            })
+            .collect();
+        errors.sort();
+        errors
+    }
+
+    /// Every app that refers to a category or an app that isn't defined.
+    pub fn validate(&self) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        for app in self.apps.values() {
+            for &category in app.cats.iter().chain(app.requires_category()) {
+                if !self.categories.contains_key(&category) {
+                    conflicts.push(Conflict::UnknownCategory {
+                        app: app.name().to_string(),
+                        category,
+                    });
+                }
+            }
+
+            let references = app
+                .implies()
+                .iter()
+                .map(|implication| ("implies", &implication.name))
+                .chain(app.excludes().iter().map(|name| ("excludes", name)))
+                .chain(app.requires().iter().map(|name| ("requires", name)));
+            for (field, target) in references {
+                if !self.apps.contains_key(target) && !self.disabled.contains(target) {
+                    conflicts.push(Conflict::DanglingReference {
+                        app: app.name().to_string(),
+                        field,
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+        conflicts.sort();
+        conflicts.dedup();
+        conflicts
+    }
+
+    /// The app called `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&App> {
+        self.apps.get(name)
+    }
+
+    pub fn apps(&self) -> impl Iterator<Item = &App> {
+        self.apps.values()
+    }
+
+    /// The number of apps.
+    pub fn len(&self) -> usize {
+        self.apps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apps.is_empty()
+    }
+
+    pub fn category_name(&self, id: u32) -> Option<&str> {
+        self.categories
+            .get(&id)
+            .map(|category| category.name.as_str())
+    }
+
+    /// The names of the groups category `id` belongs to.
+    pub fn group_names(&self, id: u32) -> Vec<&str> {
+        self.categories
+            .get(&id)
+            .map(|category| {
+                category
+                    .groups
+                    .iter()
+                    .filter_map(|group| self.groups.get(group))
+                    .map(|group| group.name.as_str())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every JS property path named by a `js` rule, e.g. `jQuery.fn.jquery`.
+    pub fn js_properties(&self) -> Vec<&str> {
+        let mut properties: Vec<&str> = self
+            .apps
+            .values()
+            .flat_map(|app| app.js().keys().map(String::as_str))
+            .collect();
+        properties.sort_unstable();
+        properties.dedup();
+        properties
+    }
+
+    /// Every DOM property named by a `dom` rule, along with the selector of
+    /// the elements to read it from.
+    pub fn dom_properties(&self) -> Vec<(&str, &str)> {
+        let mut properties: Vec<(&str, &str)> = self
+            .apps
+            .values()
+            .flat_map(|app| app.dom())
+            .flat_map(|(selector, rule)| {
+                rule.properties
+                    .keys()
+                    .map(move |property| (selector.as_str(), property.as_str()))
+            })
+            .collect();
+        properties.sort_unstable();
+        properties.dedup();
+        properties
+    }
+}
+
+impl FromStr for Technologies {
+    type Err = WappError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Technologies::from_json(text)
+    }
+}
+
+/// Why the rules in `field` are never checked, for the rule types that need
+/// more than the page: scans don't fetch stylesheets, watch XHR requests, look
+/// up DNS records or request other paths of the site.
+fn unchecked(field: &str) -> Option<String> {
+    let rule = field.split('.').next().unwrap_or_default();
+    let needs = match rule {
+        "css" => "the page's stylesheets",
+        "xhr" => "the page's XHR requests",
+        "dns" => "the site's DNS records",
+        "probe" => "other paths of the site",
+        "robots" => "the site's robots.txt",
+        _ => return None,
+    };
+    Some(format!("is never checked: {} rules need {}", rule, needs))
+}
+
+fn definitions_error(err: serde_json::Error) -> WappError {
+    WappError::Definitions(err.to_string())
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> WappError {
+    WappError::Definitions(format!("{}: {}", path.display(), err))
+}
+
+/// Names the file a parse error came from.
+fn in_file(path: &Path, err: WappError) -> WappError {
+    match err {
+        WappError::Definitions(err) => {
+            WappError::Definitions(format!("{}: {}", path.display(), err))
+        }
+        err => err,
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, WappError> {
+    let file = std::fs::File::open(path).map_err(|err| io_error(path, err))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|err| in_file(path, definitions_error(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APPS_JSON: &str = r#"{
+        "apps": {
+            "Foo": {
+                "cats": [7, 1],
+                "website": "https://foo.example",
+                "html": "<div id=\"foo\"",
+                "js": {"Foo.version": "([\\d.]+)\\;version:\\1"},
+                "implies": "Bar"
+            },
+            "Bar": {
+                "cats": [99],
+                "website": "https://bar.example"
+            }
+        },
+        "categories": {
+            "1": {"name": "CMS", "priority": 1}
+        }
+    }"#;
+
+    #[test]
+    fn test_from_json() {
+        let technologies: Technologies = APPS_JSON.parse().unwrap();
+        assert_eq!(technologies.len(), 2);
+        assert_eq!(technologies.get("Foo").unwrap().category_name(), "CMS");
+        assert_eq!(technologies.get("Bar").unwrap().category_name(), "");
+        assert_eq!(technologies.js_properties(), vec!["Foo.version"]);
+
+        let from_reader = Technologies::from_reader(APPS_JSON.as_bytes()).unwrap();
+        assert_eq!(from_reader.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        assert!(matches!(
+            Technologies::from_json("{\"apps\": {}}"),
+            Err(WappError::Definitions(_))
+        ));
+        assert!(matches!(
+            Technologies::from_path("/nonexistent/apps.json"),
+            Err(WappError::Definitions(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = std::env::temp_dir().join(format!("wappalyzer-split-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("technologies")).unwrap();
+        std::fs::write(
+            dir.join("technologies/a.json"),
+            r##"{
+                "Acme CMS": {
+                    "cats": [1],
+                    "website": "https://acme.example",
+                    "scriptSrc": "acme\\.js",
+                    "meta": {"generator": ["^Acme", "^AcmeCMS"]},
+                    "dom": "#acme",
+                    "text": "Powered by Acme",
+                    "dns": {"MX": "acme\\.example"},
+                    "requiresCategory": 6,
+                    "pricing": ["freemium"],
+                    "oss": true
+                }
+            }"##,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("technologies/b.json"),
+            r##"{"Bolt": {"cats": [1], "website": "https://bolt.example", "dom": {"#bolt": {"attributes": {"data-v": "([\\d.]+)\\;version:\\1"}}}}}"##,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("categories.json"),
+            r#"{"1": {"name": "CMS", "priority": 1, "groups": [3]}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("groups.json"), r#"{"3": {"name": "Content"}}"#).unwrap();
+
+        let technologies = Technologies::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let technologies = technologies.unwrap();
+
+        assert_eq!(technologies.len(), 2);
+        assert_eq!(technologies.group_names(1), vec!["Content"]);
+        let acme = technologies.get("Acme CMS").unwrap();
+        assert_eq!(acme.category_name(), "CMS");
+        assert_eq!(acme.script()[0].source(), "acme\\.js");
+        assert_eq!(acme.meta()["generator"].len(), 2);
+        assert!(acme.dom()["#acme"].exists.is_some());
+        assert_eq!(acme.dns()["MX"].len(), 1);
+        assert_eq!(acme.requires_category(), &[6]);
+        assert_eq!(acme.pricing(), &["freemium".to_string()]);
+        assert_eq!(acme.oss(), Some(true));
+        let bolt = technologies.get("Bolt").unwrap();
+        assert!(bolt.dom()["#bolt"].attributes["data-v"].is_match("1.2"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut technologies: Technologies = APPS_JSON.parse().unwrap();
+        let layer = Layer::from_json(
+            r#"{
+                "apps": {
+                    "Foo": {"cats": [2], "website": "", "html": "foo-v2", "implies": "Baz"},
+                    "Qux": {"cats": [1], "website": "", "implies": "Bar"}
+                },
+                "categories": {"1": {"name": "Sites"}, "2": {"name": "Intranet"}},
+                "disable": ["Bar", "Quux"]
+            }"#,
+        )
+        .unwrap();
+
+        let conflicts = technologies.merge(layer);
+        assert_eq!(
+            conflicts,
+            vec![
+                Conflict::Overridden {
+                    kind: "app",
+                    name: "Foo".to_string(),
+                },
+                Conflict::Overridden {
+                    kind: "category",
+                    name: "1".to_string(),
+                },
+                Conflict::UnknownDisabled("Quux".to_string()),
+                Conflict::DanglingReference {
+                    app: "Foo".to_string(),
+                    field: "implies",
+                    target: "Baz".to_string(),
+                },
+            ]
+        );
+        assert!(technologies.get("Bar").is_none());
+        let foo = technologies.get("Foo").unwrap();
+        assert_eq!(foo.category_name(), "Intranet");
+        assert_eq!(foo.html()[0].source(), "foo-v2");
+        assert_eq!(technologies.get("Qux").unwrap().category_name(), "Sites");
+    }
+
+    #[test]
+    fn test_validate() {
+        let technologies: Technologies = APPS_JSON.parse().unwrap();
+        assert_eq!(
+            technologies.validate(),
+            vec![
+                Conflict::UnknownCategory {
+                    app: "Bar".to_string(),
+                    category: 99,
+                },
+                Conflict::UnknownCategory {
+                    app: "Foo".to_string(),
+                    category: 7,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        let technologies = Technologies::embedded();
+        let errors: Vec<(String, String)> = technologies
+            .pattern_errors()
+            .into_iter()
+            .map(|error| (error.app, error.field))
+            .collect();
+        // A repeated look-ahead is only valid in JavaScript.
+        assert_eq!(errors, vec![("Zeuscart".to_string(), "html".to_string())]);
+        assert!(technologies.pattern_errors()[0]
+            .reason
+            .contains("look-ahead"));
+        // These need look-around, so they run on the backtracking engine.
+        for (app, field) in [("ADPLAN", "script"), ("RDoc", "html")] {
+            let patterns = technologies.get(app).unwrap().patterns();
+            assert!(patterns
+                .iter()
+                .any(|(f, pattern)| f == field && pattern.is_backtracking()));
+        }
+
+        let technologies = Technologies::from_json(
+            r#"{"apps": {"Foo": {"cats": [1], "website": "", "html": "(foo"}}, "categories": {}}"#,
+        )
+        .unwrap();
+        let errors = technologies.pattern_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].app.as_str(), errors[0].field.as_str()),
+            ("Foo", "html")
+        );
+
+        // Selectors that don't parse, and rule types that scans don't check,
+        // which are kept apart.
+        let technologies = Technologies::from_json(
+            r#"{"apps": {"Foo": {"cats": [1], "website": "", "dns": {"MX": "foo"}, "robots": "foo", "dom": "div[", "text": "foo"}}, "categories": {}}"#,
+        )
+        .unwrap();
+        let fields = |errors: Vec<PatternError>| -> Vec<String> {
+            errors.into_iter().map(|error| error.field).collect()
+        };
+        assert_eq!(fields(technologies.pattern_errors()), ["dom.div["]);
+        assert_eq!(fields(technologies.unchecked_rules()), ["dns.MX", "robots"]);
+
+        let technologies = Technologies::from_json(
+            r#"{"apps": {"Foo": {"cats": [1], "website": "", "html": "^(a+)+\\1c"}}, "categories": {}}"#,
+        )
+        .unwrap();
+        assert!(technologies.exhausted_patterns().is_empty());
+        let foo = technologies.get("Foo").unwrap();
+        assert!(!foo.html()[0].is_match(&"a".repeat(40)));
+        let exhausted = technologies.exhausted_patterns();
+        assert_eq!(exhausted.len(), 1);
+        assert_eq!(exhausted[0].field, "html");
+    }
+
+    #[test]
+    fn test_embedded() {
+        let technologies = Technologies::embedded();
+        assert!(technologies.get("WordPress").is_some());
+        assert!(technologies.js_properties().contains(&"jQuery.fn.jquery"));
+    }
+}
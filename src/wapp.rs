@@ -1,75 +1,375 @@
 use serde::de;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use futures::future::join_all;
-use regex::Regex;
-use std::collections::HashMap;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use url::Url;
 
+use crate::cookie::name_matches;
+pub use crate::cookie::Cookie;
+use crate::heuristics::split_pattern;
 use crate::heuristics::VersionInference;
+use crate::pattern::{map_of_one_or_more, one_or_more, Pattern};
+use crate::technologies::Technologies;
+use crate::WappError;
 
 extern crate lazy_static;
 
-// The build.rs build script reads the local apps.json file at build time
-// and includes the text string as a constant in a created 'apps.json.rs'
-// in the build dir. Here, we include this constant.
-include!(concat!(env!("OUT_DIR"), "/apps.json.rs"));
-
-/// A very simple representation for cookie data
-#[derive(Debug, PartialEq)]
-pub struct Cookie {
-    pub name: String,
-    pub value: String,
+/// A redirect response on the way to the page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redirect {
+    pub url: String,
+    pub status: u16,
+    pub headers: HashMap<String, String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct RawData {
+    /// The headers of the page itself, after any redirects.
     pub headers: HashMap<String, String>,
+    /// The redirects followed to reach the page, in order.
+    pub redirects: Vec<Redirect>,
     pub cookies: Vec<Cookie>,
-    pub meta_tags: HashMap<String, String>,
+    /// The content of the `<meta>` tags, in document order, by their `name`,
+    /// `property`, `http-equiv` or `itemprop` in lowercase. Private so that
+    /// every name goes through [`RawData::add_meta`] and stays lowercase.
+    meta_tags: HashMap<String, Vec<String>>,
+    /// The `<script>` tags of the page, markup included, for the `scripts`
+    /// rules.
     pub script_tags: Vec<String>,
+    /// URLs of the scripts the page loaded, from `<script src>` and the network.
+    pub script_srcs: Vec<String>,
+    /// Values of the JS properties named by the `js` rules, as found in the page.
+    pub js: HashMap<String, String>,
+    /// Values of the DOM properties named by the `dom` rules, by selector and
+    /// then property, from the first element that has one. Only a browser can
+    /// read them.
+    pub dom: HashMap<String, HashMap<String, String>>,
+    /// The URL that was scanned.
+    pub url: String,
+    /// The URL of the page after any redirects.
+    pub final_url: String,
+    /// The HTTP status of the page, when it is known. Fetchers return error
+    /// pages too, with their status, and leave it to the caller to skip them.
+    pub status: Option<u16>,
     pub html: String,
 }
 
-pub async fn check(raw_data: Arc<RawData>) -> Vec<Tech> {
-    let mut futures: Vec<tokio::task::JoinHandle<Option<Vec<Tech>>>> = vec![];
+impl RawData {
+    /// The scanned URL and, if it redirected elsewhere, the final URL.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        let final_url = Some(self.final_url.as_str()).filter(|url| *url != self.url);
+        std::iter::once(self.url.as_str())
+            .chain(final_url)
+            .filter(|url| !url.is_empty())
+    }
 
-    for app in APPS_JSON_DATA.apps.values() {
-        futures.push(app.tech_tokio(raw_data.clone()));
+    /// Builds the page data from a page fetched by other means: the scripts and
+    /// meta tags are taken from `html`, and the cookies from any `Set-Cookie`
+    /// headers, which are separated by newlines as browsers report them.
+    /// Header names may be in any case. `url` is the URL the page was served
+    /// from; script URLs are resolved against it, or against the page's
+    /// `<base href>` when it has one.
+    pub fn from_html_and_headers(
+        url: &str,
+        html: &str,
+        headers: HashMap<String, String>,
+    ) -> RawData {
+        let headers: HashMap<String, String> = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_lowercase(), value))
+            .collect();
+        let document = Html::parse_document(html);
+        let base = Url::parse(url).ok().map(|url| {
+            document
+                .select(&BASE)
+                .next()
+                .and_then(|base| url.join(base.value().attr("href")?).ok())
+                .unwrap_or(url)
+        });
+        let mut script_tags = vec![];
+        let mut script_srcs = vec![];
+        for script in document.select(&SCRIPT) {
+            script_tags.push(script.html());
+            if let Some(src) = script.value().attr("src") {
+                let src = match base.as_ref().and_then(|base| base.join(src).ok()) {
+                    Some(src) => src.to_string(),
+                    None => src.to_string(),
+                };
+                script_srcs.push(src);
+            }
+        }
+
+        RawData {
+            cookies: set_cookies(url, &headers),
+            meta_tags: meta_tags(&document),
+            script_tags,
+            script_srcs,
+            url: url.to_string(),
+            final_url: url.to_string(),
+            html: html.to_string(),
+            headers,
+            ..RawData::default()
+        }
     }
 
-    join_all(futures)
+    /// The content of the meta tags called `name`, whatever its case.
+    pub fn meta(&self, name: &str) -> &[String] {
+        self.meta_tags
+            .get(&name.to_lowercase())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Every meta tag's content, in document order, by name in lowercase.
+    pub fn meta_tags(&self) -> &HashMap<String, Vec<String>> {
+        &self.meta_tags
+    }
+
+    /// Adds the content of a meta tag called `name`, after those of the same
+    /// name whatever its case.
+    pub fn add_meta(&mut self, name: &str, content: &str) {
+        self.meta_tags
+            .entry(name.to_lowercase())
+            .or_default()
+            .push(content.to_string());
+    }
+}
+
+/// The content of the page's meta tags, by `name`, `property`, `http-equiv` and
+/// `itemprop` in lowercase. Open Graph tags use `property` and a name can
+/// repeat, so each keeps every value in document order.
+fn meta_tags(document: &Html) -> HashMap<String, Vec<String>> {
+    let mut meta_tags: HashMap<String, Vec<String>> = HashMap::new();
+    for meta in document.select(&META) {
+        let element = meta.value();
+        let content = element.attr("content").unwrap_or_default();
+        for attribute in ["name", "property", "http-equiv", "itemprop"] {
+            if let Some(name) = element.attr(attribute).map(str::trim) {
+                if !name.is_empty() {
+                    meta_tags
+                        .entry(name.to_lowercase())
+                        .or_default()
+                        .push(content.to_string());
+                }
+            }
+        }
+    }
+    meta_tags
+}
+
+/// The cookies set by the `Set-Cookie` headers of a response from `url`.
+pub(crate) fn set_cookies(url: &str, headers: &HashMap<String, String>) -> Vec<Cookie> {
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string));
+    headers
+        .get("set-cookie")
+        .into_iter()
+        .flat_map(|set_cookie| set_cookie.lines())
+        .filter_map(|set_cookie| Cookie::parse(set_cookie, host.as_deref()))
+        .collect()
+}
+
+/// Which parts of the page rules are matched against, beyond the defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MatchOptions {
+    /// Match `headers` rules against the redirects too, not just the page.
+    pub redirect_headers: bool,
+    /// Keep the rules that matched, and what they matched, in each
+    /// [`Tech::evidence`].
+    pub evidence: bool,
+}
+
+/// Checks the page against every app in `technologies`, off the async threads.
+/// A panic while checking is reported as [`WappError::Analyze`] rather than as
+/// a page without technologies.
+pub async fn check(
+    technologies: Arc<Technologies>,
+    raw_data: Arc<RawData>,
+    options: MatchOptions,
+) -> Result<Vec<Tech>, WappError> {
+    tokio::task::spawn_blocking(move || detect(&technologies, &raw_data, options))
         .await
-        .iter()
-        .filter_map(|r| r.as_ref().ok())
-        .filter(|o| o.is_some())
-        .map(|r| r.as_ref().unwrap().to_owned())
-        .flat_map(|a| a.into_iter())
-        .collect::<Vec<_>>()
+        .map_err(|err| WappError::Analyze(err.to_string()))
 }
 
-lazy_static! {
-    static ref APPS_JSON_DATA: AppsJsonData = {
-        let mut apps_json_data: AppsJsonData =
-            serde_json::from_str(APPS_JSON_TEXT).expect("Error loading the apps.json file");
+/// Checks the page against every app in `technologies`, as `options` say.
+pub fn detect(technologies: &Technologies, raw_data: &RawData, options: MatchOptions) -> Vec<Tech> {
+    let page = Page::new(raw_data);
+    let (independent, mut pending): (Vec<&App>, Vec<&App>) =
+        technologies.apps().partition(|app| !app.has_requirements());
+    let mut detected = independent
+        .into_iter()
+        .filter_map(|app| app.tech(technologies, &page, options))
+        .flatten()
+        .collect::<Vec<_>>();
+
+    // Apps with `requires` or `requiresCategory` are only checked once what
+    // they require has been detected, which can be another such app, so this
+    // goes on until a round has nothing left to check.
+    loop {
+        let names: HashSet<&str> = detected.iter().map(|tech| tech.name.as_str()).collect();
+        let categories: HashSet<u32> = names
+            .iter()
+            .filter_map(|name| technologies.get(name))
+            .flat_map(|app| app.cats.iter().copied())
+            .collect();
+        let (ready, waiting): (Vec<&App>, Vec<&App>) = pending
+            .into_iter()
+            .partition(|app| app.requirements_met(&names, &categories));
+        pending = waiting;
+        if ready.is_empty() {
+            break;
+        }
+        let dependent = ready
+            .into_iter()
+            .filter_map(|app| app.tech(technologies, &page, options))
+            .flatten()
+            .collect::<Vec<_>>();
+        detected.extend(dependent);
+    }
 
-        for (app_name, app) in apps_json_data.apps.iter_mut() {
-            (*app).name = String::from(app_name);
+    resolve_excludes(technologies, merge_detections(detected))
+}
+
+/// Merges the detections of the same technology: an app can be both detected
+/// and implied, or implied more than once. The most confident of them and the
+/// most specific of their versions are kept, so the result doesn't depend on
+/// the order of the apps.
+fn merge_detections(detected: Vec<Tech>) -> Vec<Tech> {
+    let mut techs: HashMap<String, Tech> = HashMap::new();
+    for tech in detected {
+        match techs.get_mut(&tech.name) {
+            Some(existing) => {
+                if tech.confidence > existing.confidence {
+                    existing.confidence = tech.confidence;
+                }
+                let versions = existing.version.take().into_iter().chain(tech.version);
+                existing.version = VERSION_INFERENCE.most_specific(versions.collect());
+                existing.evidence.extend(tech.evidence);
+            }
+            None => {
+                techs.insert(tech.name.clone(), tech);
+            }
         }
+    }
+    for tech in techs.values_mut() {
+        tech.evidence.sort();
+        tech.evidence.dedup();
+    }
+    techs.into_values().collect()
+}
 
-        apps_json_data
+/// Drops the technologies that another detected technology excludes. Implied
+/// technologies are part of the detected set, so their exclusions apply too.
+/// An exclusion is a conflict between two detections, which the stronger one
+/// wins: the more confident, then the one with the higher `priority`, then the
+/// first by name, so that the result doesn't depend on the order of the apps.
+/// The technologies are taken strongest first and each is kept unless it
+/// conflicts with one already kept, so a technology that lost doesn't exclude
+/// others in turn.
+fn resolve_excludes(technologies: &Technologies, mut techs: Vec<Tech>) -> Vec<Tech> {
+    let priority = |tech: &Tech| technologies.get(&tech.name).map_or(0, |app| app.priority);
+    techs.sort_by(|a, b| {
+        b.confidence
+            .cmp(&a.confidence)
+            .then_with(|| priority(b).cmp(&priority(a)))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let excludes = |tech: &Tech, other: &Tech| {
+        tech.name != other.name
+            && technologies
+                .get(&tech.name)
+                .is_some_and(|app| app.excludes.contains(&other.name))
     };
+    let mut kept: Vec<Tech> = Vec::with_capacity(techs.len());
+    for tech in techs {
+        if !kept
+            .iter()
+            .any(|other| excludes(other, &tech) || excludes(&tech, other))
+        {
+            kept.push(tech);
+        }
+    }
+    kept
+}
+
+lazy_static! {
     static ref VERSION_INFERENCE: VersionInference = VersionInference::new_default();
+    static ref BODY: Selector = Selector::parse("body").unwrap();
+    static ref SCRIPT: Selector = Selector::parse("script").unwrap();
+    static ref META: Selector = Selector::parse("meta[content]").unwrap();
+    static ref BASE: Selector = Selector::parse("base[href]").unwrap();
+}
+
+/// The page data along with its parsed document, for the `dom` and `text`
+/// rules. The document is parsed once however many apps are checked.
+pub(crate) struct Page<'a> {
+    pub(crate) raw_data: &'a RawData,
+    document: Html,
+    /// The text of the body, without scripts and styles.
+    pub(crate) text: String,
+}
+
+impl<'a> Page<'a> {
+    pub(crate) fn new(raw_data: &'a RawData) -> Page<'a> {
+        let document = Html::parse_document(&raw_data.html);
+        let text = document
+            .select(&BODY)
+            .next()
+            .map(|body| visible_text(&body))
+            .unwrap_or_default();
+        Page {
+            raw_data,
+            document,
+            text,
+        }
+    }
+
+    /// The elements the selector of `rule` matches, none when it isn't a
+    /// valid selector.
+    pub(crate) fn select(&self, rule: &DomRule) -> Vec<ElementRef<'_>> {
+        match &rule.selector {
+            Some(selector) => self.document.select(selector).collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// The text under `element` that a visitor would see, one line per text node.
+fn visible_text(element: &ElementRef) -> String {
+    element
+        .descendants()
+        .filter(|node| {
+            !node.ancestors().any(|ancestor| {
+                ancestor.value().as_element().is_some_and(|element| {
+                    matches!(element.name(), "script" | "style" | "noscript" | "template")
+                })
+            })
+        })
+        .filter_map(|node| node.value().as_text())
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 /// A technology that is found on a page
-#[derive(Debug, PartialEq, Eq, Hash,  Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Tech {
     pub category: String,
     pub name: String,
     pub version: Option<String>,
+    /// How sure the detection is, from 0 to 100.
+    pub confidence: u32,
+    /// The rules that matched, and what they matched.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<Evidence>,
 }
 impl Tech {
     /// let tech = Tech::named("webpack");
@@ -82,7 +382,6 @@ impl Tech {
     //         None
     //     }
     // }
-
     pub fn from(app: &App) -> Tech {
         Tech::from_with_version(app, None)
     }
@@ -92,92 +391,326 @@ impl Tech {
             name: app.name.clone(),
             category: app.category_name(),
             version,
+            confidence: 100,
+            evidence: vec![],
         }
     }
+
+    pub fn with_confidence(mut self, confidence: u32) -> Tech {
+        self.confidence = confidence;
+        self
+    }
+
+    pub fn with_evidence(mut self, evidence: Vec<Evidence>) -> Tech {
+        self.evidence = evidence;
+        self
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AppsJsonData {
-    apps: HashMap<String, App>,
-    categories: HashMap<u32, Category>,
+/// Where on the page a rule matched.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum EvidenceSource {
+    /// A response header, by name.
+    Header(String),
+    /// A response header of one of the redirects to the page, by name.
+    RedirectHeader(String),
+    /// A cookie, by name.
+    Cookie(String),
+    /// A meta tag, by name.
+    Meta(String),
+    Html,
+    /// A script, by URL.
+    ScriptSrc(String),
+    /// The content of one of the page's `<script>` tags.
+    Script,
+    /// A JS property, by path.
+    Js(String),
+    /// The page's URL or the URL it ended up at, by URL.
+    Url(String),
+    /// The elements a CSS selector matches, by selector.
+    Dom(String),
+    /// The visible text of the page.
+    Text,
+    /// Implied by another detected technology, by name.
+    Implied(String),
 }
-impl AppsJsonData {
-    // fn named(&self, name: &str) -> Option<&App> {
-    //     self.apps.get(&String::from(name))
-    // }
 
-    fn category_name(&self, id: u32) -> Option<String> {
-        match self.categories.get(&id) {
-            // Some(category) => Some(String::from(category.name)),
-            Some(category) => Some(category.name.clone()),
-            None => None,
+/// How much of the text a rule matched is kept, in characters; `html` rules
+/// can match whole blocks of markup.
+const MAX_MATCHED_LEN: usize = 200;
+
+/// A rule that matched the page: where, which pattern, and the text it matched,
+/// cut to [`MAX_MATCHED_LEN`] characters and ending in `…` when it was longer.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
+pub struct Evidence {
+    pub source: EvidenceSource,
+    pub pattern: String,
+    pub matched: String,
+}
+
+impl Evidence {
+    fn new(source: EvidenceSource, pattern: &Pattern, matched: &str) -> Evidence {
+        Evidence {
+            source,
+            pattern: pattern.source().to_string(),
+            matched: match matched.char_indices().nth(MAX_MATCHED_LEN) {
+                Some((end, _)) => format!("{}…", &matched[..end]),
+                None => matched.to_string(),
+            },
         }
     }
 }
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct App {
     #[serde(skip)]
-    name: String,
-    cats: Vec<u32>,
+    pub(crate) name: String,
+    /// The name of the app's first category, filled in when it is loaded.
+    #[serde(skip)]
+    pub(crate) category: String,
+    pub(crate) cats: Vec<u32>,
     website: String,
     #[serde(default)]
     priority: i32,
-    #[serde(deserialize_with = "one_or_more_strings")]
+    #[serde(deserialize_with = "one_or_more")]
     #[serde(default)]
-    html: Vec<String>,
+    html: Vec<Pattern>,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: HashMap<String, Pattern>,
     #[serde(default)]
-    cookies: HashMap<String, String>,
+    cookies: HashMap<String, Pattern>,
     #[serde(default)]
-    js: HashMap<String, String>,
+    js: HashMap<String, Pattern>,
+    #[serde(deserialize_with = "one_or_more")]
     #[serde(default)]
-    url: String,
-    #[serde(default)]
-    meta: HashMap<String, String>,
+    url: Vec<Pattern>,
+    #[serde(default, deserialize_with = "map_of_one_or_more")]
+    meta: HashMap<String, Vec<Pattern>>,
     #[serde(default)]
     icon: String,
-    #[serde(deserialize_with = "one_or_more_strings")]
+    #[serde(deserialize_with = "one_or_more")]
     #[serde(default)]
-    implies: Vec<String>,
+    implies: Vec<Implication>,
     #[serde(default)]
     #[serde(deserialize_with = "one_or_more_strings")]
     excludes: Vec<String>,
+    /// `scriptSrc` in the split upstream format.
+    #[serde(default, alias = "scriptSrc")]
+    #[serde(deserialize_with = "one_or_more")]
+    script: Vec<Pattern>,
+    /// Patterns for the content of the page's scripts, in the split upstream
+    /// format.
+    #[serde(default, deserialize_with = "one_or_more")]
+    scripts: Vec<Pattern>,
+    #[serde(default, deserialize_with = "dom_rules")]
+    dom: HashMap<String, DomRule>,
+    #[serde(default, deserialize_with = "one_or_more")]
+    text: Vec<Pattern>,
+    #[serde(default, deserialize_with = "one_or_more")]
+    css: Vec<Pattern>,
+    #[serde(default, deserialize_with = "one_or_more")]
+    xhr: Vec<Pattern>,
+    #[serde(default, deserialize_with = "map_of_one_or_more")]
+    dns: HashMap<String, Vec<Pattern>>,
     #[serde(default)]
-    #[serde(deserialize_with = "one_or_more_strings")]
-    script: Vec<String>,
+    probe: HashMap<String, Pattern>,
+    #[serde(default, deserialize_with = "one_or_more")]
+    robots: Vec<Pattern>,
+    #[serde(default, deserialize_with = "one_or_more_strings")]
+    requires: Vec<String>,
+    #[serde(default, rename = "requiresCategory")]
+    #[serde(deserialize_with = "one_or_more")]
+    requires_category: Vec<u32>,
+    #[serde(default)]
+    pricing: Vec<String>,
+    #[serde(default)]
+    saas: Option<bool>,
+    #[serde(default)]
+    oss: Option<bool>,
 }
 
 impl App {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn category_name(&self) -> String {
-        assert!(!self.cats.is_empty());
-        APPS_JSON_DATA.category_name(self.cats[0]).unwrap()
+        self.category.clone()
+    }
+
+    /// The IDs of all the app's categories; [`App::category_name`] is the
+    /// first one's.
+    pub fn cats(&self) -> &[u32] {
+        &self.cats
     }
 
-    pub fn headers(&self) -> &HashMap<String, String> {
+    pub fn headers(&self) -> &HashMap<String, Pattern> {
         &self.headers
     }
 
-    pub fn cookies(&self) -> &HashMap<String, String> {
+    pub fn cookies(&self) -> &HashMap<String, Pattern> {
         &self.cookies
     }
 
-    pub fn meta(&self) -> &HashMap<String, String> {
+    pub fn meta(&self) -> &HashMap<String, Vec<Pattern>> {
         &self.meta
     }
 
-    pub fn html(&self) -> &[String] {
+    pub fn html(&self) -> &[Pattern] {
         &self.html
     }
 
-    pub fn script(&self) -> &[String] {
+    pub fn script(&self) -> &[Pattern] {
         &self.script
     }
 
-    pub fn js(&self) -> &HashMap<String, String> {
+    pub fn scripts(&self) -> &[Pattern] {
+        &self.scripts
+    }
+
+    pub fn js(&self) -> &HashMap<String, Pattern> {
         &self.js
     }
 
+    pub fn url(&self) -> &[Pattern] {
+        &self.url
+    }
+
+    pub fn implies(&self) -> &[Implication] {
+        &self.implies
+    }
+
+    pub fn excludes(&self) -> &[String] {
+        &self.excludes
+    }
+
+    /// The DOM rules, by CSS selector.
+    pub fn dom(&self) -> &HashMap<String, DomRule> {
+        &self.dom
+    }
+
+    /// Patterns for the visible text of the page.
+    pub fn text(&self) -> &[Pattern] {
+        &self.text
+    }
+
+    /// Patterns for the page's stylesheets.
+    pub fn css(&self) -> &[Pattern] {
+        &self.css
+    }
+
+    /// Patterns for the host names of the page's XHR requests.
+    pub fn xhr(&self) -> &[Pattern] {
+        &self.xhr
+    }
+
+    /// Patterns for DNS records, by record type, e.g. `MX`.
+    pub fn dns(&self) -> &HashMap<String, Vec<Pattern>> {
+        &self.dns
+    }
+
+    /// Patterns for the responses of other paths on the site, by path.
+    pub fn probe(&self) -> &HashMap<String, Pattern> {
+        &self.probe
+    }
+
+    /// Patterns for the site's robots.txt.
+    pub fn robots(&self) -> &[Pattern] {
+        &self.robots
+    }
+
+    /// The apps this app is only checked alongside.
+    pub fn requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    /// The categories this app is only checked alongside.
+    pub fn requires_category(&self) -> &[u32] {
+        &self.requires_category
+    }
+
+    /// Pricing tags, e.g. `freemium` or `recurring`.
+    pub fn pricing(&self) -> &[String] {
+        &self.pricing
+    }
+
+    pub fn saas(&self) -> Option<bool> {
+        self.saas
+    }
+
+    pub fn oss(&self) -> Option<bool> {
+        self.oss
+    }
+
+    /// Every pattern of the app along with the field it belongs to, e.g.
+    /// `html` or `headers.X-Powered-By`.
+    pub fn patterns(&self) -> Vec<(String, &Pattern)> {
+        let mut patterns = vec![];
+        let lists = [
+            ("html", &self.html),
+            ("script", &self.script),
+            ("scripts", &self.scripts),
+            ("url", &self.url),
+            ("text", &self.text),
+            ("css", &self.css),
+            ("xhr", &self.xhr),
+            ("robots", &self.robots),
+        ];
+        for (field, list) in lists {
+            patterns.extend(list.iter().map(|pattern| (field.to_string(), pattern)));
+        }
+        let maps = [
+            ("headers", &self.headers),
+            ("cookies", &self.cookies),
+            ("js", &self.js),
+            ("probe", &self.probe),
+        ];
+        for (field, map) in maps {
+            for (key, pattern) in map {
+                patterns.push((format!("{}.{}", field, key), pattern));
+            }
+        }
+        for (field, map) in [("meta", &self.meta), ("dns", &self.dns)] {
+            for (key, list) in map {
+                patterns.extend(list.iter().map(|p| (format!("{}.{}", field, key), p)));
+            }
+        }
+        for (selector, rule) in &self.dom {
+            let field = format!("dom.{}", selector);
+            let single = [("exists", &rule.exists), ("text", &rule.text)];
+            for (key, pattern) in single {
+                if let Some(pattern) = pattern {
+                    patterns.push((format!("{}.{}", field, key), pattern));
+                }
+            }
+            for (key, map) in [
+                ("attributes", &rule.attributes),
+                ("properties", &rule.properties),
+            ] {
+                for (name, pattern) in map {
+                    patterns.push((format!("{}.{}.{}", field, key, name), pattern));
+                }
+            }
+        }
+        patterns
+    }
+
+    fn has_requirements(&self) -> bool {
+        !self.requires.is_empty() || !self.requires_category.is_empty()
+    }
+
+    /// Whether one of the apps or categories this app requires was detected.
+    fn requirements_met(&self, names: &HashSet<&str>, categories: &HashSet<u32>) -> bool {
+        self.requires
+            .iter()
+            .any(|name| names.contains(name.as_str()))
+            || self
+                .requires_category
+                .iter()
+                .any(|id| categories.contains(id))
+    }
+
     // pub fn check_headers(&self,)
     // pub async fn tech(
     //     &self,
@@ -194,48 +727,117 @@ impl App {
     //     }
     // }
 
-    pub fn tech_tokio(
-        &'static self,
-        raw_data: Arc<RawData>,
-    ) -> tokio::task::JoinHandle<Option<Vec<Tech>>> {
-        tokio::spawn(async move {
-            if self.check(raw_data.clone()) {
-                let mut tech = vec![];
-                for i in &self.implies {
-                    let app = APPS_JSON_DATA.apps.get(i)?;
-                    let version = VERSION_INFERENCE.infer(app, raw_data.as_ref());
-                    tech.push(Tech::from_with_version(app, version));
-                }
-                let version = VERSION_INFERENCE.infer(self, raw_data.as_ref());
-                tech.push(Tech::from_with_version(self, version));
-                Some(tech)
+    /// This app and the apps it implies, when the page uses it.
+    pub(crate) fn tech(
+        &self,
+        technologies: &Technologies,
+        page: &Page,
+        options: MatchOptions,
+    ) -> Option<Vec<Tech>> {
+        let evidence = self.matches(page, options);
+        let confidence = confidence(&evidence);
+        if confidence > 0 {
+            let version = VERSION_INFERENCE.infer(self, page, options);
+            let evidence = if options.evidence {
+                evidence.into_iter().map(|(_, e)| e).collect()
             } else {
-                None
+                vec![]
+            };
+            let mut tech = vec![Tech::from_with_version(self, version)
+                .with_confidence(confidence)
+                .with_evidence(evidence)];
+            for (app, implied_by, implication) in self.implied_apps(technologies) {
+                let version = match &implication.version {
+                    Some(version) => Some(version.clone()),
+                    None => VERSION_INFERENCE.infer(app, page, options),
+                };
+                // An implied app is no more certain than the app implying it.
+                let confidence = confidence.min(implication.confidence.unwrap_or(100));
+                let evidence = Evidence {
+                    source: EvidenceSource::Implied(implied_by.name.clone()),
+                    pattern: implication.source.clone(),
+                    matched: implied_by.name.clone(),
+                };
+                tech.push(
+                    Tech::from_with_version(app, version)
+                        .with_confidence(confidence)
+                        .with_evidence(options.evidence.then_some(evidence).into_iter().collect()),
+                );
             }
-        })
+            Some(tech)
+        } else {
+            None
+        }
+    }
+
+    /// Every app this app implies, directly or through other implied apps,
+    /// along with the app and the implication that led to it. Each app appears
+    /// once, so cycles end where they started, and names missing from
+    /// `technologies` are skipped.
+    pub fn implied_apps<'a>(
+        &'a self,
+        technologies: &'a Technologies,
+    ) -> Vec<(&'a App, &'a App, &'a Implication)> {
+        let mut seen: HashSet<&str> = HashSet::new();
+        seen.insert(self.name.as_str());
+
+        let mut implied = vec![];
+        let mut queue: VecDeque<&App> = VecDeque::from([self]);
+        while let Some(app) = queue.pop_front() {
+            for implication in &app.implies {
+                if let Some(implied_app) = technologies.get(&implication.name) {
+                    if seen.insert(implied_app.name.as_str()) {
+                        implied.push((implied_app, app, implication));
+                        queue.push_back(implied_app);
+                    }
+                }
+            }
+        }
+        implied
+    }
+
+    /// The confidence that the page uses this app: the sum of the confidence of
+    /// every matching rule, capped at 100. Zero means that nothing matched.
+    pub fn check(&self, raw_data: Arc<RawData>) -> u32 {
+        confidence(&self.evidence(raw_data.as_ref(), MatchOptions::default()))
+    }
+
+    /// Every rule of this app that matches the page, as `options` say.
+    pub fn evidence(&self, raw_data: &RawData, options: MatchOptions) -> Vec<(&Pattern, Evidence)> {
+        self.matches(&Page::new(raw_data), options)
     }
 
-    // TODO: initially only checking for one positive
-    pub fn check(&self, raw_data: Arc<RawData>) -> bool {
+    fn matches(&self, page: &Page, options: MatchOptions) -> Vec<(&Pattern, Evidence)> {
+        let raw_data = page.raw_data;
+        let mut matched = vec![];
+
         // check headers
         for (header_to_check, expected_value) in self.headers.iter() {
-
-            if let Some(value) = raw_data.headers.get(header_to_check.to_lowercase().as_str()) {
-                   if check_text(expected_value, value.as_str()) {
-                        //eprintln!(
-                        //    "||| HEADER ({}) hit on: {}",
-                        //    header_to_check, expected_value
-                        //);
-                        return true;
+            let name = header_to_check.to_lowercase();
+            if let Some(m) = raw_data
+                .headers
+                .get(&name)
+                .and_then(|value| expected_value.find(value))
+            {
+                let source = EvidenceSource::Header(name);
+                matched.push((expected_value, Evidence::new(source, expected_value, m)));
+            } else if options.redirect_headers {
+                // A rule counts once, however many redirects it matches.
+                if let Some(m) = raw_data
+                    .redirects
+                    .iter()
+                    .find_map(|redirect| expected_value.find(redirect.headers.get(&name)?))
+                {
+                    let source = EvidenceSource::RedirectHeader(name);
+                    matched.push((expected_value, Evidence::new(source, expected_value, m)));
                 }
             }
         }
 
         // html
-        for maybe_regex in self.html.iter() {
-            if check_text(maybe_regex, &raw_data.html) {
-                // eprintln!("||| HTML hit on: {}", maybe_regex);
-                return true; // TODO: temp impletation that returns on any hit
+        for pattern in self.html.iter() {
+            if let Some(m) = pattern.find(&raw_data.html) {
+                matched.push((pattern, Evidence::new(EvidenceSource::Html, pattern, m)));
             }
         }
 
@@ -251,81 +853,284 @@ impl App {
             // COOKIE: Cookie { cookie_string: Some("1P_JAR=2019-09-18-19; expires=Fri, 18-Oct-2019 19:05:14 GMT; path=/; domain=.google.com; SameSite=none"), name: Indexed(0, 6), value: Indexed(7, 20), expires: Some(Tm { tm_sec: 14, tm_min: 5, tm_hour: 19, tm_mday: 18, tm_mon: 9, tm_year: 119, tm_wday: 5, tm_yday: 0, tm_isdst: 0, tm_utcoff: 0, tm_nsec: 0 }), max_age: None, domain: Some(Indexed(77, 87)), path: Some(Indexed(66, 67)), secure: None, http_only: None, same_site: None }
             // COOKIE: Cookie { cookie_string: Some("NID=188=E7jfAOxVZYeABbEwAi-4RN6pK1a-98zWM1hcFnt8bjHM_303Gon7qmJCopif_taWAwwNrpB9bcjQXn1Mm9gRzIagJSoLll4Wp0XHrPtBUMIXN58jCbdQFVEKAz1yJgyi_oxdG6NVYB2An8_RWmJ-EWp-6umHMMatZfxTAyE2-n8; expires=Thu, 19-Mar-2020 19:05:14 GMT; path=/; domain=.google.com; HttpOnly"), name: Indexed(0, 3), value: Indexed(4, 179), expires: Some(Tm { tm_sec: 14, tm_min: 5, tm_hour: 19, tm_mday: 19, tm_mon: 2, tm_year: 120, tm_wday: 4, tm_yday: 0, tm_isdst: 0, tm_utcoff: 0, tm_nsec: 0 }), max_age: None, domain: Some(Indexed(236, 246)), path: Some(Indexed(225, 226)), secure: None, http_only: Some(true), same_site: None }
 
-            // loop through and find the appropriate cookie
-            if let Some(c) = raw_data.cookies.iter().find(|c| {
-                // eprintln!("COOKIE: ({})==({})", c.name(), cookies_to_check);
-                c.name == *cookies_to_check
-            }) {
-                // an empty expected_value means that we only care about the existence if the cookie
-                if expected_value.is_empty() || check_text(expected_value, &c.value) {
-                    // eprintln!("||| COOKIE ({}) hit on: {}", c.value, expected_value);
-                    return true; // TODO: Temp impl where one hit returns
+            // loop through and find the appropriate cookie; a name ending in
+            // `*` matches every cookie it is a prefix of
+            // an empty expected_value matches anything, so only the existence of the cookie matters
+            if let Some((c, m)) = raw_data
+                .cookies
+                .iter()
+                .filter(|c| name_matches(cookies_to_check, &c.name))
+                .find_map(|c| expected_value.find(&c.value).map(|m| (c, m)))
+            {
+                let source = EvidenceSource::Cookie(c.name.clone());
+                matched.push((expected_value, Evidence::new(source, expected_value, m)));
+            }
+        }
+
+        // script
+        for pattern in self.script.iter() {
+            for src in &raw_data.script_srcs {
+                if let Some(m) = pattern.find(src) {
+                    let source = EvidenceSource::ScriptSrc(src.clone());
+                    matched.push((pattern, Evidence::new(source, pattern, m)));
+                    break;
                 }
             }
         }
 
-        // try just checking for the js_to_check value, as (1) the js version seems to use the dom directly, and
-        // (2) the Go version doesn't seem to work
-        for (js_to_check, _rule_value) in self.js.iter() {
-            for js in &raw_data.script_tags {
-                if check_text(js_to_check, js) {
-                    // eprintln!("||| JS hit on: {}", js_to_check);
-                    return true;
+        // scripts
+        for pattern in self.scripts.iter() {
+            if let Some(m) = raw_data
+                .script_tags
+                .iter()
+                .find_map(|script| pattern.find(script))
+            {
+                matched.push((pattern, Evidence::new(EvidenceSource::Script, pattern, m)));
+            }
+        }
+
+        // js
+        for (js_to_check, expected_value) in self.js.iter() {
+            if let Some(value) = raw_data.js.get(js_to_check) {
+                if let Some(m) = expected_value.find(value) {
+                    let source = EvidenceSource::Js(js_to_check.clone());
+                    matched.push((expected_value, Evidence::new(source, expected_value, m)));
                 }
             }
         }
 
+        // url
+        for pattern in self.url.iter() {
+            if let Some((url, m)) = raw_data
+                .urls()
+                .find_map(|url| pattern.find(url).map(|m| (url, m)))
+            {
+                let source = EvidenceSource::Url(url.to_string());
+                matched.push((pattern, Evidence::new(source, pattern, m)));
+            }
+        }
+
         // meta
-        for (meta_to_check, expected_value) in self.meta.iter() {
-            if let Some(value) = raw_data.meta_tags.get(meta_to_check) {
-                // an empty expected_value means that we only care about the existence if the cookie
-                if check_text(expected_value, value) {
-                    // eprintln!(
-                    //     "||| META ({}) hit on: {} for value: {}",
-                    //     meta_to_check, expected_value, value
-                    // );
-                    return true; // TODO: Temp impl where one hit returns
+        for (meta_to_check, expected_values) in self.meta.iter() {
+            let values = raw_data.meta(meta_to_check);
+            for expected_value in expected_values {
+                if let Some(m) = values.iter().find_map(|value| expected_value.find(value)) {
+                    let source = EvidenceSource::Meta(meta_to_check.clone());
+                    matched.push((expected_value, Evidence::new(source, expected_value, m)));
                 }
             }
         }
 
-        // check html
-        false
+        // dom
+        for (selector, rule) in self.dom.iter() {
+            let elements = page.select(rule);
+            let source = || EvidenceSource::Dom(selector.clone());
+            if let Some(pattern) = &rule.exists {
+                if let Some(m) = elements.first().and_then(|_| pattern.find("")) {
+                    matched.push((pattern, Evidence::new(source(), pattern, m)));
+                }
+            }
+            if let Some(pattern) = &rule.text {
+                for element in &elements {
+                    let text = element.text().collect::<String>();
+                    if let Some(m) = pattern.find(&text) {
+                        matched.push((pattern, Evidence::new(source(), pattern, m)));
+                        break;
+                    }
+                }
+            }
+            for (name, pattern) in rule.attributes.iter() {
+                if let Some(m) = elements
+                    .iter()
+                    .find_map(|element| pattern.find(element.value().attr(name)?))
+                {
+                    matched.push((pattern, Evidence::new(source(), pattern, m)));
+                }
+            }
+            for (name, pattern) in rule.properties.iter() {
+                if let Some(m) = raw_data
+                    .dom
+                    .get(selector)
+                    .and_then(|properties| pattern.find(properties.get(name)?))
+                {
+                    matched.push((pattern, Evidence::new(source(), pattern, m)));
+                }
+            }
+        }
+
+        // text
+        for pattern in self.text.iter() {
+            if let Some(m) = pattern.find(&page.text) {
+                matched.push((pattern, Evidence::new(EvidenceSource::Text, pattern, m)));
+            }
+        }
+
+        matched
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Category {
-    name: String,
-    priority: u8,
+/// The sum of the confidence of the matched rules, capped at 100.
+fn confidence(evidence: &[(&Pattern, Evidence)]) -> u32 {
+    evidence
+        .iter()
+        .map(|(pattern, _)| pattern.confidence())
+        .sum::<u32>()
+        .min(100)
 }
 
-// The meat of the matter
-fn check_text(maybe_regex: &str, text: &str) -> bool {
-    // TODO: strignoring version stuff for now.
-    // TODO: Compile regex's in the initialization area.
-    let maybe_regex = format!("(?i){}", maybe_regex);
-    let maybe_regex = maybe_regex
-        .splitn(2, "\\;")
-        .next()
-        .unwrap_or(maybe_regex.as_str());
-    match Regex::new(maybe_regex) {
-        Ok(re) => {
-            //println!("REGEX IS FINE: [{}] - trying on [{}] and got {:?}", maybe_regex, text, re.is_match(text));
-
-            re.is_match(text)
+/// An entry of an app's `implies`, e.g. `PHP\;confidence:75`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Implication {
+    source: String,
+    pub name: String,
+    pub confidence: Option<u32>,
+    pub version: Option<String>,
+}
+
+impl Implication {
+    pub fn new(source: &str) -> Implication {
+        let (name, tags) = split_pattern(source);
+        Implication {
+            source: source.to_string(),
+            name: name.trim().to_string(),
+            confidence: tags.confidence,
+            version: tags.version.and_then(|version| version.literal()),
         }
+    }
+}
+
+impl Serialize for Implication {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Implication {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+        Ok(Implication::new(&source))
+    }
+}
+
+/// What to look for in the elements a CSS selector matches. A rule with nothing
+/// but `exists` matches as soon as the selector does.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DomRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exists: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub attributes: HashMap<String, Pattern>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, Pattern>,
+    /// The selector the rule is under, parsed once when it is loaded.
+    #[serde(skip)]
+    selector: Option<Selector>,
+    #[serde(skip)]
+    error: Option<String>,
+}
+
+impl DomRule {
+    fn parse_selector(&mut self, selector: &str) {
+        match Selector::parse(selector) {
+            Ok(selector) => self.selector = Some(selector),
+            Err(err) => self.error = Some(format!("is not a valid selector: {:?}", err)),
+        }
+    }
+
+    /// Why the selector doesn't parse, in which case the rule never matches.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+/// Deserializes `dom`, which is a selector, a list of selectors or a map of
+/// selectors to rules.
+fn dom_rules<'de, D>(deserializer: D) -> Result<HashMap<String, DomRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DomRules {
+        Selectors(#[serde(deserialize_with = "one_or_more")] Vec<String>),
+        Rules(HashMap<String, DomRule>),
+    }
+
+    let exists = |selector: String| {
+        let rule = DomRule {
+            exists: Some(Pattern::new("")),
+            ..DomRule::default()
+        };
+        (selector, rule)
+    };
+    let mut rules: HashMap<String, DomRule> = match DomRules::deserialize(deserializer) {
+        Ok(DomRules::Selectors(selectors)) => selectors.into_iter().map(exists).collect(),
+        Ok(DomRules::Rules(rules)) => rules,
         Err(_) => {
-             //eprintln!("invalid regex in app.json '{}': {}", maybe_regex, err);
-             //panic!("invalid regex in app.json '{}': {}", maybe_regex, err);
-             false
+            return Err(de::Error::custom(
+                "expected a selector, a list of selectors or a map of selectors to rules",
+            ))
         }
+    };
+    for (selector, rule) in rules.iter_mut() {
+        rule.parse_selector(selector);
     }
+    Ok(rules)
+}
+
+fn one_or_more_strings<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct StringOrVec(PhantomData<Vec<String>>);
+
+    impl<'de> de::Visitor<'de> for StringOrVec {
+        type Value = Vec<String>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("string or list of strings")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_owned()])
+        }
+
+        fn visit_seq<S>(self, visitor: S) -> Result<Self::Value, S::Error>
+        where
+            S: de::SeqAccess<'de>,
+        {
+            Deserialize::deserialize(de::value::SeqAccessDeserializer::new(visitor))
+        }
+    }
+
+    deserializer.deserialize_any(StringOrVec(PhantomData))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn app(name: &str) -> &'static App {
+        lazy_static! {
+            static ref TECHNOLOGIES: Arc<Technologies> = Technologies::embedded();
+        }
+        TECHNOLOGIES.get(name).unwrap()
+    }
+
+    fn check_text(maybe_regex: &str, text: &str) -> bool {
+        Pattern::new(maybe_regex).is_match(text)
+    }
     // use reqwest::header;
 
     // #[test]
@@ -350,10 +1155,469 @@ mod tests {
     //     // );
     // }
 
+    #[test]
+    fn test_apps_json_patterns() {
+        let wordpress = app("WordPress");
+        assert!(wordpress.meta()["generator"][0].is_match("WordPress 5.2"));
+        assert!(!wordpress.script().is_empty());
+    }
+
+    #[test]
+    fn test_js_rules() {
+        let aframe = app("A-Frame");
+        let mut raw_data = RawData::default();
+        assert_eq!(aframe.check(Arc::new(RawData::default())), 0);
+
+        raw_data
+            .js
+            .insert("AFRAME.version".to_string(), "1.0.4".to_string());
+        assert_eq!(
+            VERSION_INFERENCE.infer(aframe, &Page::new(&raw_data), MatchOptions::default()),
+            Some("1.0.4".to_string())
+        );
+        assert_eq!(aframe.check(Arc::new(raw_data)), 100);
+    }
+
+    #[test]
+    fn test_resolve_excludes() {
+        let named = |name: &str| Tech::from(app(name));
+        let techs = vec![
+            named("Underscore.js"),
+            named("Lodash"),
+            named("Woosa"),
+            named("WordPress"),
+            named("PHP"),
+        ];
+        let names: Vec<String> = resolve_excludes(&Technologies::embedded(), techs)
+            .into_iter()
+            .map(|tech| tech.name)
+            .collect();
+        assert_eq!(names, vec!["Lodash", "PHP", "Woosa"]);
+
+        // The alphabetically later app wins when it is more confident.
+        let techs = vec![
+            Tech {
+                confidence: 50,
+                ..named("Lodash")
+            },
+            named("Underscore.js"),
+        ];
+        let names: Vec<String> = resolve_excludes(&Technologies::embedded(), techs)
+            .into_iter()
+            .map(|tech| tech.name)
+            .collect();
+        assert_eq!(names, vec!["Underscore.js"]);
+
+        // A excludes B and B excludes C: C stays unless B wins over A.
+        let technologies = Technologies::from_json(
+            r#"{
+                "apps": {
+                    "A": {"cats": [1], "website": "", "excludes": "B"},
+                    "B": {"cats": [1], "website": "", "excludes": "C"},
+                    "C": {"cats": [1], "website": ""}
+                },
+                "categories": {"1": {"name": "CMS"}}
+            }"#,
+        )
+        .unwrap();
+        let resolve = |confidences: [u32; 3]| -> Vec<String> {
+            let techs = ["A", "B", "C"]
+                .iter()
+                .zip(confidences)
+                .map(|(name, confidence)| Tech {
+                    confidence,
+                    ..Tech::from(technologies.get(name).unwrap())
+                })
+                .collect();
+            let mut names: Vec<String> = resolve_excludes(&technologies, techs)
+                .into_iter()
+                .map(|tech| tech.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(resolve([100, 100, 100]), vec!["A", "C"]);
+        assert_eq!(resolve([50, 100, 100]), vec!["B"]);
+        assert_eq!(resolve([100, 75, 50]), vec!["A", "C"]);
+    }
+
+    #[test]
+    fn test_implied_apps() {
+        let implication = Implication::new("PHP\\;confidence:75\\;version:7");
+        assert_eq!(implication.name, "PHP");
+        assert_eq!(implication.confidence, Some(75));
+        assert_eq!(implication.version, Some("7".to_string()));
+
+        let technologies = Technologies::embedded();
+        let acquia = technologies.get("Acquia Cloud").unwrap();
+        let implied: Vec<&str> = acquia
+            .implied_apps(&technologies)
+            .into_iter()
+            .map(|(app, _, _)| app.name.as_str())
+            .collect();
+        // Drupal is implied directly and in turn implies PHP.
+        assert!(implied.contains(&"Drupal"));
+        assert!(implied.contains(&"PHP"));
+        assert!(!implied.contains(&"Acquia Cloud"));
+    }
+
+    #[test]
+    fn test_check_confidence() {
+        let rails = app("Ruby on Rails");
+        let mut raw_data = RawData::default();
+        raw_data.cookies.push(Cookie::new("_session_id", "abc"));
+        assert_eq!(rails.check(Arc::new(raw_data)), 75);
+
+        let mut raw_data = RawData::default();
+        raw_data.cookies.push(Cookie::new("_session_id", "abc"));
+        raw_data.add_meta("csrf-param", "authenticity_token");
+        assert_eq!(rails.check(Arc::new(raw_data)), 100);
+    }
+
+    #[test]
+    fn test_meta_tags() {
+        let document = Html::parse_document(
+            r#"<html><head>
+                <meta name="Generator" content="WordPress 6.4">
+                <meta name="generator" content="Elementor 3.18">
+                <meta property="og:site_name" content="Example">
+                <meta http-equiv="X-Powered-By" content="PHP/8.2">
+                <meta itemprop="name" content="Example">
+                <meta charset="utf-8">
+            </head></html>"#,
+        );
+        let meta_tags = meta_tags(&document);
+        assert_eq!(meta_tags["generator"], ["WordPress 6.4", "Elementor 3.18"]);
+        assert_eq!(meta_tags["og:site_name"], ["Example"]);
+        assert_eq!(meta_tags["x-powered-by"], ["PHP/8.2"]);
+        assert_eq!(meta_tags["name"], ["Example"]);
+        assert_eq!(meta_tags.len(), 4);
+    }
+
+    #[test]
+    fn test_from_html_and_headers() {
+        let mut headers = HashMap::new();
+        headers.insert("X-Powered-By".to_string(), "PHP/8.2".to_string());
+        headers.insert(
+            "Set-Cookie".to_string(),
+            "PHPSESSID=abc; path=/\nlang=en".to_string(),
+        );
+        let raw_data = RawData::from_html_and_headers(
+            "https://example.com/blog/",
+            r#"<html><head>
+                <meta name="generator" content="WordPress 6.4">
+                <script src="/wp-includes/js/jquery/jquery.min.js?ver=3.7.1"></script>
+                <script>var x = 1;</script>
+            </head></html>"#,
+            headers,
+        );
+        assert_eq!(raw_data.headers["x-powered-by"], "PHP/8.2");
+        assert_eq!(raw_data.meta("generator"), ["WordPress 6.4"]);
+        assert_eq!(
+            raw_data.script_srcs,
+            ["https://example.com/wp-includes/js/jquery/jquery.min.js?ver=3.7.1"]
+        );
+        assert_eq!(raw_data.script_tags.len(), 2);
+        assert_eq!(raw_data.cookies.len(), 2);
+        assert_eq!(raw_data.cookies[0].name, "PHPSESSID");
+        assert_eq!(raw_data.cookies[0].domain.as_deref(), Some("example.com"));
+        assert_eq!(raw_data.final_url, "https://example.com/blog/");
+
+        let names: Vec<String> = detect(
+            &Technologies::embedded(),
+            &raw_data,
+            MatchOptions::default(),
+        )
+        .into_iter()
+        .map(|tech| tech.name)
+        .collect();
+        assert!(names.contains(&"WordPress".to_string()));
+        assert!(names.contains(&"PHP".to_string()));
+
+        // Relative script URLs follow the page's <base href>.
+        let raw_data = RawData::from_html_and_headers(
+            "https://example.com/blog/",
+            r#"<!DOCTYPE html><html><head><base href="https://cdn.example.net/assets/">
+                <script src="app.js"></script></head><body></body></html>"#,
+            HashMap::new(),
+        );
+        assert_eq!(
+            raw_data.script_srcs,
+            ["https://cdn.example.net/assets/app.js"]
+        );
+    }
+
+    #[test]
+    fn test_evidence() {
+        let wordpress = app("WordPress");
+        let mut raw_data = RawData::default();
+        raw_data.add_meta("generator", "Yoast SEO");
+        raw_data.add_meta("Generator", "WordPress 5.2.1");
+        assert_eq!(raw_data.meta("GENERATOR").len(), 2);
+        assert_eq!(raw_data.meta_tags().len(), 1);
+
+        let evidence: Vec<Evidence> = wordpress
+            .evidence(&raw_data, MatchOptions::default())
+            .into_iter()
+            .map(|(_, evidence)| evidence)
+            .collect();
+        assert_eq!(
+            evidence,
+            vec![Evidence {
+                source: EvidenceSource::Meta("generator".to_string()),
+                pattern: "^WordPress ?([\\d.]+)?\\;version:\\1".to_string(),
+                matched: "WordPress 5.2.1".to_string(),
+            }]
+        );
+        assert_eq!(
+            serde_json::to_string(&evidence[0].source).unwrap(),
+            "{\"kind\":\"meta\",\"name\":\"generator\"}"
+        );
+
+        let technologies = Technologies::from_json(
+            r#"{
+                "apps": {"Shop": {"cats": [6], "website": "", "html": "<div id=\"shop\">.*", "url": "^https://shop\\."}},
+                "categories": {"6": {"name": "Ecommerce"}}
+            }"#,
+        )
+        .unwrap();
+        let raw_data = RawData {
+            url: "https://shop.example.com/".to_string(),
+            html: format!("<div id=\"shop\">{}", "x".repeat(1000)),
+            ..RawData::default()
+        };
+        assert!(detect(&technologies, &raw_data, MatchOptions::default())[0]
+            .evidence
+            .is_empty());
+
+        let options = MatchOptions {
+            evidence: true,
+            ..MatchOptions::default()
+        };
+        let techs = detect(&technologies, &raw_data, options);
+        let evidence = &techs[0].evidence;
+        assert_eq!(evidence.len(), 2);
+        assert_eq!(evidence[0].source, EvidenceSource::Html);
+        assert_eq!(evidence[0].matched.chars().count(), MAX_MATCHED_LEN + 1);
+        assert!(evidence[0].matched.ends_with('…'));
+        assert_eq!(
+            evidence[1].source,
+            EvidenceSource::Url("https://shop.example.com/".to_string())
+        );
+        assert_eq!(evidence[1].matched, "https://shop.");
+    }
+
+    #[test]
+    fn test_url_rules() {
+        let bigcommerce = app("Bigcommerce");
+        let raw_data = RawData {
+            url: "http://shop.example.com/".to_string(),
+            final_url: "https://store-abc.mybigcommerce.com/".to_string(),
+            ..RawData::default()
+        };
+        assert_eq!(bigcommerce.check(Arc::new(raw_data)), 100);
+
+        let raw_data = RawData {
+            url: "http://shop.example.com/".to_string(),
+            final_url: "http://shop.example.com/".to_string(),
+            ..RawData::default()
+        };
+        assert_eq!(bigcommerce.check(Arc::new(raw_data)), 0);
+    }
+
+    #[test]
+    fn test_redirect_headers() {
+        let iis = app("IIS");
+        let raw_data = RawData {
+            redirects: vec![Redirect {
+                url: "http://example.com/".to_string(),
+                status: 301,
+                headers: HashMap::from([("server".to_string(), "Microsoft-IIS/10.0".to_string())]),
+            }],
+            ..RawData::default()
+        };
+        assert!(iis.evidence(&raw_data, MatchOptions::default()).is_empty());
+
+        let options = MatchOptions {
+            redirect_headers: true,
+            ..MatchOptions::default()
+        };
+        let evidence = iis.evidence(&raw_data, options);
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(
+            evidence[0].1.source,
+            EvidenceSource::RedirectHeader("server".to_string())
+        );
+        let techs = detect(&Technologies::embedded(), &raw_data, options);
+        let iis = techs.iter().find(|tech| tech.name == "IIS").unwrap();
+        assert_eq!(iis.version.as_deref(), Some("10.0"));
+    }
+
+    #[test]
+    fn test_wildcard_cookies() {
+        let technologies = Technologies::from_json(
+            r#"{
+                "apps": {"hapi": {"cats": [18], "website": "", "cookies": {"Fe26.2**": "\\;confidence:50"}}},
+                "categories": {"18": {"name": "Web frameworks"}}
+            }"#,
+        )
+        .unwrap();
+        let hapi = technologies.get("hapi").unwrap();
+        let mut raw_data = RawData::default();
+        raw_data.cookies.push(Cookie::new("session", "Fe26.2**abc"));
+        assert_eq!(hapi.check(Arc::new(raw_data)), 0);
+
+        let mut raw_data = RawData::default();
+        raw_data.cookies.push(Cookie::new("Fe26.2**f00d", "abc"));
+        let evidence = hapi.evidence(&raw_data, MatchOptions::default());
+        assert_eq!(
+            evidence[0].1.source,
+            EvidenceSource::Cookie("Fe26.2**f00d".to_string())
+        );
+        assert_eq!(hapi.check(Arc::new(raw_data)), 50);
+    }
+
+    #[test]
+    fn test_merged_version() {
+        // Shop is both detected, as version 5, and implied as 5.2.1; the more
+        // specific version wins whichever comes first.
+        let technologies = Technologies::from_json(
+            r#"{
+                "apps": {
+                    "Shop": {"cats": [6], "website": "", "html": "shop-(\\d+)\\;version:\\1"},
+                    "Theme": {"cats": [6], "website": "", "html": "theme-x", "implies": "Shop\\;version:5.2.1"}
+                },
+                "categories": {"6": {"name": "Ecommerce"}}
+            }"#,
+        )
+        .unwrap();
+        let shop = technologies.get("Shop").unwrap();
+        let evidence = |source| Evidence {
+            source,
+            pattern: String::new(),
+            matched: String::new(),
+        };
+        let detected = Tech::from_with_version(shop, Some("5".to_string()))
+            .with_confidence(50)
+            .with_evidence(vec![evidence(EvidenceSource::Html)]);
+        let implied = Tech::from_with_version(shop, Some("5.2.1".to_string()))
+            .with_evidence(vec![evidence(EvidenceSource::Implied("Theme".to_string()))]);
+        for detections in [
+            vec![detected.clone(), implied.clone()],
+            vec![implied, detected],
+        ] {
+            let techs = merge_detections(detections);
+            assert_eq!(techs.len(), 1);
+            assert_eq!(techs[0].version.as_deref(), Some("5.2.1"));
+            assert_eq!(techs[0].confidence, 100);
+            let mut sorted = techs[0].evidence.clone();
+            sorted.sort();
+            assert_eq!(techs[0].evidence, sorted);
+            assert_eq!(sorted.len(), 2);
+        }
+
+        // And through detection.
+        let raw_data = RawData {
+            html: "shop-5 theme-x".to_string(),
+            ..RawData::default()
+        };
+        let techs = detect(&technologies, &raw_data, MatchOptions::default());
+        assert_eq!(techs.len(), 2);
+        let shop = techs.iter().find(|tech| tech.name == "Shop").unwrap();
+        assert_eq!(shop.version.as_deref(), Some("5.2.1"));
+    }
+
+    #[test]
+    fn test_requires() {
+        let technologies = Technologies::from_json(
+            r#"{
+                "apps": {
+                    "Shop": {"cats": [6], "website": "", "html": "shop-cart"},
+                    "Shop Theme": {"cats": [1], "website": "", "html": "theme-x", "requires": "Shop"},
+                    "Shop Plugin": {"cats": [1], "website": "", "html": "plugin-y", "requiresCategory": 6},
+                    "Theme Addon": {"cats": [1], "website": "", "html": "addon-z", "requires": "Shop Theme"}
+                },
+                "categories": {"1": {"name": "CMS"}, "6": {"name": "Ecommerce"}}
+            }"#,
+        )
+        .unwrap();
+        let names = |html: &str| -> Vec<String> {
+            let raw_data = RawData {
+                html: html.to_string(),
+                ..RawData::default()
+            };
+            detect(&technologies, &raw_data, MatchOptions::default())
+                .into_iter()
+                .map(|tech| tech.name)
+                .collect()
+        };
+
+        assert_eq!(names("theme-x plugin-y"), Vec::<String>::new());
+        assert_eq!(
+            names("shop-cart theme-x plugin-y"),
+            vec!["Shop", "Shop Plugin", "Shop Theme"]
+        );
+        // Requirements chain: the addon needs the theme, which needs the shop.
+        assert_eq!(
+            names("shop-cart theme-x addon-z"),
+            vec!["Shop", "Shop Theme", "Theme Addon"]
+        );
+    }
+
+    #[test]
+    fn test_dom_and_text_rules() {
+        let technologies = Technologies::from_json(
+            r##"{
+                "apps": {
+                    "Acme": {"cats": [1], "website": "", "dom": "#acme"},
+                    "Bolt": {"cats": [1], "website": "", "dom": {"div.bolt": {"attributes": {"data-v": "([\\d.]+)\\;version:\\1"}}}},
+                    "Vue": {"cats": [1], "website": "", "dom": {"#app": {"properties": {"__vue__": ""}}}},
+                    "Shopify": {"cats": [1], "website": "", "text": "Powered by Shopify"},
+                    "Hidden": {"cats": [1], "website": "", "text": "var hidden"},
+                    "Inline": {"cats": [1], "website": "", "scripts": "var hidden = (\\d)\\;version:\\1"}
+                },
+                "categories": {"1": {"name": "CMS"}}
+            }"##,
+        )
+        .unwrap();
+        let mut raw_data = RawData {
+            html: r#"<html><body><div id="acme"></div><div class="bolt" data-v="2.1.0"><p>Powered by Shopify</p></div><script>var hidden = 1;</script></body></html>"#.to_string(),
+            script_tags: vec!["<script>var hidden = 1;</script>".to_string()],
+            ..RawData::default()
+        };
+        let options = MatchOptions {
+            evidence: true,
+            ..MatchOptions::default()
+        };
+        let mut techs = detect(&technologies, &raw_data, options);
+        techs.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = techs.iter().map(|tech| tech.name.as_str()).collect();
+        assert_eq!(names, ["Acme", "Bolt", "Inline", "Shopify"]);
+        assert_eq!(techs[1].version.as_deref(), Some("2.1.0"));
+        assert_eq!(
+            techs[1].evidence[0].source,
+            EvidenceSource::Dom("div.bolt".to_string())
+        );
+        assert_eq!(techs[2].version.as_deref(), Some("1"));
+        assert_eq!(techs[2].evidence[0].source, EvidenceSource::Script);
+        assert_eq!(techs[3].evidence[0].source, EvidenceSource::Text);
+
+        // Properties come from the browser.
+        raw_data.dom.insert(
+            "#app".to_string(),
+            HashMap::from([("__vue__".to_string(), "true".to_string())]),
+        );
+        let techs = detect(&technologies, &raw_data, MatchOptions::default());
+        assert!(techs.iter().any(|tech| tech.name == "Vue"));
+        assert_eq!(technologies.dom_properties(), [("#app", "__vue__")]);
+    }
+
     #[test]
     fn test_check_text() {
         assert!(check_text("foo", "somefood"));
         assert!(!check_text("bar", "somefood"));
+        assert!(check_text("foo(?=d)", "somefood"));
+        assert!(!check_text("foo(?!d)", "somefood"));
         assert!(check_text("[CK]amva", "Kamva"));
         assert!(!check_text("[CK]amva", "Lamva"));
         assert!(check_text(
@@ -386,34 +1650,3 @@ mod tests {
         // ));
     }
 }
-
-fn one_or_more_strings<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    struct StringOrVec(PhantomData<Vec<String>>);
-
-    impl<'de> de::Visitor<'de> for StringOrVec {
-        type Value = Vec<String>;
-
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("string or list of strings")
-        }
-
-        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            Ok(vec![value.to_owned()])
-        }
-
-        fn visit_seq<S>(self, visitor: S) -> Result<Self::Value, S::Error>
-        where
-            S: de::SeqAccess<'de>,
-        {
-            Deserialize::deserialize(de::value::SeqAccessDeserializer::new(visitor))
-        }
-    }
-
-    deserializer.deserialize_any(StringOrVec(PhantomData))
-}